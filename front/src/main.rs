@@ -1,47 +1,29 @@
 use stdweb::{__js_raw_asm, js, js_export};
 
-#[js_export]
-// wraps spoodly::interpret and provides the web STD.
-fn interpret(src: String) -> String {
-    use spoodly::{eval::Var, Context, Raw};
-
-    // start with the normal STD, and override it as neccessary.
-    let mut webstd = Context::std();
-    webstd.map.insert(
-        "DISPLAY".to_string(),
-        Var::Function(Box::new(|args: Vec<Var>| {
-            //eprintln!("args len: {}", args.len());
-            let output = args.iter().fold(String::new(), |acc, arg| {
-                format!("{} {}", acc, arg).trim().to_owned()
-            });
-
-            {
-                let output = output.clone();
-                js! { display(@{output}) };
-            }
-
-            Var::Raw(Raw::Text(output))
-        })),
-    );
-    webstd.map.insert(
-        "INPUT".to_string(),
-        Var::Function(Box::new(|mut args: Vec<Var>| {
-            //eprintln!("args len: {}", args.len());
-            let prompt = format!(
-                "{}",
-                args.pop()
-                    .unwrap_or(Var::Raw(Raw::Text("input".to_string())))
-            );
+/// Sends DISPLAYs to the page, and asks the page for INPUT.
+struct Web;
 
-            let input = js! { return input(@{prompt}); }
-                .into_string()
-                .expect("didn't give string in input");
+impl spoodly::Host for Web {
+    fn output(&mut self, text: &str) {
+        let output = text.to_string();
+        js! { display(@{output}) };
+    }
 
-            Var::Raw(Raw::Text(input))
-        })),
-    );
+    fn input(&mut self, prompt: &str) -> Result<String, spoodly::RuntimeError> {
+        let prompt = match prompt {
+            "" => "input".to_string(),
+            prompt => prompt.to_string(),
+        };
+        Ok(js! { return input(@{prompt}); }
+            .into_string()
+            .expect("didn't give string in input"))
+    }
+}
 
-    match spoodly::interpret(src, webstd) {
+#[js_export]
+// wraps spoodly::interpret and provides the web STD.
+fn interpret(src: String) -> String {
+    match spoodly::interpret(src, spoodly::Context::with_host(Web)) {
         // nobody wants to see the normal program output for the time being.
         Ok(_) => String::new(),
         Err(msg) => msg,
@@ -0,0 +1,367 @@
+/// A Chunk of bytecode is compiled from an Abstract Syntax Tree.
+/// Rather than walking the tree each time it's run, the Evaluator steps through the flat list of
+/// instructions inside of the Chunk, keeping track of its values on a stack.
+pub mod op;
+pub use op::{Op, Slot};
+
+mod resolve;
+use resolve::{locals, Resolver};
+
+use super::{
+    ast::{Control, Node},
+    Raw,
+};
+use std::sync::Arc;
+
+/// Instructions, along with the literal values and identifiers that they refer to.
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// The line of source code each instruction came from.
+    pub lines: Vec<u32>,
+    pub constants: Vec<Raw>,
+    pub names: Vec<String>,
+    /// The name of the procedure this Chunk is the body of, if it has one.
+    pub name: Option<String>,
+    /// The names of the locals in the scope this Chunk gets when it's called, in slot order.
+    /// The first few are the parameters it takes.
+    pub locals: Arc<[String]>,
+    /// How many parameters the Chunk takes.
+    pub arity: u32,
+    /// The bodies of the lambdas defined in this Chunk, compiled separately so that they can be
+    /// run whenever the lambda is called.
+    pub lambdas: Vec<Arc<Chunk>>,
+}
+
+/// A compiler turns an Abstract Syntax Tree into a Chunk.
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    resolver: Resolver,
+    /// The line of source code that's being compiled.
+    line: u32,
+}
+impl Compiler {
+    /// Compiles a sequence of nodes so that they leave exactly one value on the stack.
+    /// Like the tree walker, if the nodes don't produce exactly one value,
+    /// whatever they did produce is gathered up into a List.
+    fn body(&mut self, nodes: &[Node]) -> Result<(), String> {
+        let mut produced = 0;
+        for node in nodes {
+            produced += self.node(node)?;
+        }
+        if produced != 1 {
+            self.emit(Op::List(produced));
+        }
+        Ok(())
+    }
+
+    /// Compiles a Node, returning how many values it leaves on the stack.
+    fn node(&mut self, node: &Node) -> Result<u32, String> {
+        if let Some(control) = node.control() {
+            self.control(control)?;
+            return Ok(0);
+        }
+
+        Ok(match node {
+            // Blocks don't get a scope of their own, like the spec says; only procedure calls do.
+            Node::Block(children) | Node::List(children) => {
+                self.body(children)?;
+                1
+            }
+            Node::Value(raw) => {
+                let index = self.constant(raw);
+                self.emit(Op::Constant(index));
+                1
+            }
+            Node::Var(id) => {
+                let slot = self.slot(id)?;
+                self.emit(Op::Load(slot));
+                1
+            }
+            Node::Line(line) => {
+                self.line = *line;
+                0
+            }
+            Node::Lambda(body) => {
+                let lambda = self.procedure(None, &[], body)?;
+                self.chunk.lambdas.push(Arc::new(lambda));
+                self.emit(Op::Lambda(self.chunk.lambdas.len() as u32 - 1));
+                1
+            }
+            Node::Assign(id, value) => {
+                self.body(std::slice::from_ref(&**value))?;
+                let slot = self.slot(id)?;
+                self.emit(Op::Store(slot));
+                0
+            }
+            Node::Call(id, args) => {
+                let mut argc = 0;
+                for arg in args {
+                    argc += self.node(arg)?;
+                }
+                let slot = self.slot(id)?;
+                self.emit(Op::Call(slot, argc));
+                1
+            }
+        })
+    }
+
+    /// Compiles a block of code that's run for its effects, throwing away any values it produces.
+    fn statement(&mut self, node: &Node) -> Result<(), String> {
+        for _ in 0..self.node(node)? {
+            self.emit(Op::Pop);
+        }
+        Ok(())
+    }
+
+    fn control(&mut self, control: Control) -> Result<(), String> {
+        match control {
+            Control::If(condition, then, otherwise) => {
+                self.body(std::slice::from_ref(condition))?;
+                let skip_then = self.emit(Op::JumpUnless(0));
+                self.statement(then)?;
+                match otherwise {
+                    Some(otherwise) => {
+                        let skip_otherwise = self.emit(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.statement(otherwise)?;
+                        self.patch(skip_otherwise);
+                    }
+                    None => self.patch(skip_then),
+                }
+            }
+            Control::RepeatTimes(count, body) => {
+                self.body(std::slice::from_ref(count))?;
+                let start = self.emit(Op::Countdown(0));
+                self.statement(body)?;
+                self.emit(Op::Jump(start as u32));
+                self.patch(start);
+            }
+            Control::RepeatUntil(condition, body) => {
+                let start = self.chunk.code.len();
+                self.body(std::slice::from_ref(condition))?;
+                let exit = self.emit(Op::JumpIf(0));
+                self.statement(body)?;
+                self.emit(Op::Jump(start as u32));
+                self.patch(exit);
+            }
+            Control::Procedure(name, parameters, body) => {
+                let parameters: Vec<String> = parameters
+                    .iter()
+                    .filter_map(|parameter| match parameter {
+                        Node::Var(id) => Some(id.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let procedure = self.procedure(Some(name), &parameters, body)?;
+                self.chunk.lambdas.push(Arc::new(procedure));
+                self.emit(Op::Lambda(self.chunk.lambdas.len() as u32 - 1));
+                let slot = self.slot(name)?;
+                self.emit(Op::Store(slot));
+            }
+            Control::Return(value) => {
+                self.body(std::slice::from_ref(value))?;
+                self.emit(Op::Return);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a reference to a variable, by finding the closest scope around it that
+    /// assigns to it. If none of them do, it's looked up by name once the Chunk is run.
+    fn slot(&mut self, id: &str) -> Result<Slot, String> {
+        if self.resolver.depth() > u16::MAX as usize {
+            return Err("procedures are nested too deeply".to_string());
+        }
+        Ok(match self.resolver.resolve(id) {
+            Some((depth, slot)) => Slot::Local(depth as u16, slot as u16),
+            None => Slot::Free(self.name(id), self.resolver.depth() as u16),
+        })
+    }
+
+    /// Compiles the body of a procedure or lambda, which gets a new scope each time it's called.
+    /// That scope's parent is the one the procedure was defined in, so variables from the code
+    /// surrounding it are resolved just like they would be outside of it.
+    fn procedure(
+        &self,
+        name: Option<&str>,
+        parameters: &[String],
+        body: &Node,
+    ) -> Result<Chunk, String> {
+        let body = std::slice::from_ref(body);
+        let locals = locals(parameters, body);
+        if locals.len() > u16::MAX as usize {
+            return Err("too many variables in one procedure".to_string());
+        }
+
+        let mut compiler = Compiler {
+            resolver: self.resolver.clone(),
+            line: self.line,
+            ..Compiler::default()
+        };
+        compiler.chunk.name = name.map(str::to_string);
+        compiler.chunk.locals = locals.clone();
+        compiler.chunk.arity = parameters.len() as u32;
+        compiler.resolver.enter(locals);
+        compiler.body(body)?;
+        Ok(compiler.chunk)
+    }
+
+    /// Adds an instruction to the end of the Chunk, returning where it was put.
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.lines.push(self.line);
+        self.chunk.code.len() - 1
+    }
+
+    /// Points the jump at the given location to the end of the Chunk.
+    fn patch(&mut self, at: usize) {
+        let here = self.chunk.code.len() as u32;
+        match &mut self.chunk.code[at] {
+            Op::Jump(to) | Op::JumpIf(to) | Op::JumpUnless(to) | Op::Countdown(to) => *to = here,
+            op => unreachable!("can't patch {:?}, it isn't a jump", op),
+        }
+    }
+
+    fn constant(&mut self, raw: &Raw) -> u32 {
+        let constants = &mut self.chunk.constants;
+        constants.iter().position(|c| c == raw).unwrap_or_else(|| {
+            constants.push(raw.clone());
+            constants.len() - 1
+        }) as u32
+    }
+
+    fn name(&mut self, id: &str) -> u32 {
+        let names = &mut self.chunk.names;
+        names.iter().position(|n| n == id).unwrap_or_else(|| {
+            names.push(id.to_string());
+            names.len() - 1
+        }) as u32
+    }
+}
+
+/// Compiles an Abstract Syntax Tree into a Chunk of bytecode that leaves one value on the stack,
+/// the same one that the tree walker would return for it.
+pub fn compile(ast: &[Node]) -> Result<Chunk, String> {
+    let mut compiler = Compiler {
+        line: 1,
+        ..Compiler::default()
+    };
+    compiler.body(ast)?;
+    Ok(compiler.chunk)
+}
+
+#[test]
+fn test_compile() {
+    use Op::*;
+    use Slot::*;
+
+    fn compile_source(source: &str) -> Chunk {
+        compile(&[crate::parse(source).expect("couldn't parse source in compile test")])
+            .expect("couldn't compile source in compile test")
+    }
+
+    assert_eq!(
+        compile_source("s <- 3 + 2"),
+        Chunk {
+            code: vec![
+                Constant(0),
+                Constant(1),
+                Call(Free(0, 0), 2),
+                Store(Free(1, 0)),
+                List(0)
+            ],
+            lines: vec![1; 5],
+            constants: vec![Raw::Integer(3), Raw::Integer(2)],
+            names: vec!["+".to_string(), "s".to_string()],
+            name: None,
+            locals: Vec::new().into(),
+            arity: 0,
+            lambdas: vec![],
+        }
+    );
+
+    assert_eq!(
+        compile_source("DISPLAY(3 3)").code,
+        [Constant(0), Constant(0), Call(Free(0, 0), 2)],
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        Load(Free(0, 0)),
+        JumpUnless(6),
+            Constant(0), Call(Free(1, 0), 1), Pop,
+        Jump(9),
+            Constant(1), Call(Free(1, 0), 1), Pop,
+        List(0),
+    ];
+    assert_eq!(
+        compile_source("IF true { DISPLAY(1) } ELSE { DISPLAY(2) }").code,
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        Constant(0),
+        Countdown(6),
+            Constant(1), Call(Free(0, 0), 1), Pop,
+        Jump(1),
+        List(0),
+    ];
+    assert_eq!(
+        compile_source("REPEAT 3 TIMES { DISPLAY(1) }").code,
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        Load(Free(0, 0)),
+        JumpIf(5),
+            Load(Free(1, 0)), Store(Free(0, 0)),
+        Jump(0),
+        List(0),
+    ];
+    assert_eq!(
+        compile_source("REPEAT UNTIL done { done <- true }").code,
+        expected
+    );
+
+    let chunk = compile_source("f <- | { DISPLAY(1) }");
+    assert_eq!(chunk.code, [Lambda(0), Store(Free(0, 0)), List(0)]);
+    assert_eq!(chunk.lambdas[0].code, [Constant(0), Call(Free(0, 1), 1)]);
+
+    let chunk = compile_source(
+        "\
+        PROCEDURE add(a, b)
+        {
+            sum <- a + b
+            RETURN(sum)
+        }",
+    );
+    assert_eq!(chunk.code, [Lambda(0), Store(Free(0, 0)), List(0)]);
+    #[rustfmt::skip]
+    let expected = [
+        Load(Local(0, 0)), Load(Local(0, 1)), Call(Free(0, 1), 2), Store(Local(0, 2)),
+        Load(Local(0, 2)), Return,
+        List(0),
+    ];
+    assert_eq!(chunk.lambdas[0].code, expected);
+    assert_eq!(
+        *chunk.lambdas[0].locals,
+        ["a", "b", "sum"].map(String::from)
+    );
+    assert_eq!(chunk.lambdas[0].arity, 2);
+
+    // lambdas reach the variables of the procedures they're defined in through their scope's parent.
+    let chunk = compile_source("PROCEDURE outer(n) { get <- | { n } }");
+    let outer = &chunk.lambdas[0];
+    assert_eq!(outer.code, [Lambda(0), Store(Local(0, 1)), List(0)]);
+    assert_eq!(outer.lambdas[0].code, [Load(Local(1, 0))]);
+
+    // every instruction knows which line of source code it came from.
+    assert_eq!(
+        compile_source("x <- 1\nIF x > 0\n{\n    DISPLAY(x)\n    x <- 0\n}").lines,
+        [1, 1, 2, 2, 2, 2, 4, 4, 5, 5, 5, 5],
+    );
+}
@@ -0,0 +1,43 @@
+/// Where the compiler found a variable.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Slot {
+    /// In the scope that many scopes out from the current one, in the slot with that index.
+    Local(u16, u16),
+    /// Somewhere outside of the Chunk, so it's looked up by name (the index of which is stored
+    /// here) starting from the scope that many scopes out. If no scope has it, it's a global.
+    Free(u32, u16),
+}
+
+/// A single instruction for the Evaluator's virtual machine.
+/// Operands index into the tables of the Chunk the instruction is found in,
+/// or into the instructions themselves in the case of jumps.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    /// Pushes a copy of a literal value onto the stack.
+    Constant(u32),
+    /// Pushes the value of a variable onto the stack.
+    Load(Slot),
+    /// Pops a value off of the stack and stores it in a variable. If it's a local that hasn't been
+    /// assigned to yet, but a procedure or lambda around the Chunk already has a variable with
+    /// the same name, that variable gets the value instead. Globals never do.
+    Store(Slot),
+    /// Calls the function or lambda in a variable, with that many arguments
+    /// popped off of the stack. Whatever it returns is pushed onto the stack.
+    Call(Slot, u32),
+    /// Pushes one of the Chunk's lambdas onto the stack.
+    Lambda(u32),
+    /// Pops that many values off of the stack and pushes them back as one List.
+    List(u32),
+    /// Throws away the value on top of the stack.
+    Pop,
+    /// Pops a value off of the stack and returns it from the Chunk being run.
+    Return,
+    Jump(u32),
+    /// Pops a boolean off of the stack, and jumps if it's true.
+    JumpIf(u32),
+    /// Pops a boolean off of the stack, and jumps if it's false.
+    JumpUnless(u32),
+    /// Counts down the number of times a `REPEAT n TIMES` loop has left to run, which is kept on
+    /// top of the stack. Once there's none left, pops the count and jumps out of the loop.
+    Countdown(u32),
+}
@@ -0,0 +1,77 @@
+use crate::ast::Node;
+use std::sync::Arc;
+
+/// Finds the names of all of the variables assigned to directly inside of a procedure or lambda,
+/// after its parameters. These are its locals, each of which is given a slot of its own.
+/// Blocks don't get scopes of their own, so everything inside of them is included, but the
+/// procedures and lambdas defined inside of them aren't.
+pub fn locals(parameters: &[String], nodes: &[Node]) -> Arc<[String]> {
+    fn add(names: &mut Vec<String>, id: &str) {
+        if !names.iter().any(|name| name == id) {
+            names.push(id.to_string());
+        }
+    }
+
+    fn find(node: &Node, names: &mut Vec<String>) {
+        if let Some(control) = node.control() {
+            use crate::ast::Control::*;
+            let (first, second, third) = match control {
+                If(condition, then, otherwise) => (condition, Some(then), otherwise),
+                RepeatTimes(count, body) => (count, Some(body), None),
+                RepeatUntil(condition, body) => (condition, Some(body), None),
+                Procedure(name, _, _) => return add(names, name),
+                Return(value) => (value, None, None),
+            };
+            for node in Some(first).iter().chain(&second).chain(&third) {
+                find(node, names);
+            }
+            return;
+        }
+
+        match node {
+            Node::Assign(id, value) => {
+                add(names, id);
+                find(value, names);
+            }
+            Node::Block(children) | Node::List(children) | Node::Call(_, children) => {
+                for child in children {
+                    find(child, names);
+                }
+            }
+            Node::Lambda(_) | Node::Value(_) | Node::Var(_) | Node::Line(_) => {}
+        }
+    }
+
+    let mut names = parameters.to_vec();
+    for node in nodes {
+        find(node, &mut names);
+    }
+    names.into()
+}
+
+/// Keeps track of the scopes surrounding the code being compiled,
+/// so that references to variables can be resolved to the slot the variable is kept in.
+#[derive(Clone, Default)]
+pub struct Resolver {
+    /// The locals of each scope, the innermost one last.
+    scopes: Vec<Arc<[String]>>,
+}
+impl Resolver {
+    pub fn enter(&mut self, locals: Arc<[String]>) {
+        self.scopes.push(locals);
+    }
+
+    /// How many scopes surround the code being compiled.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Returns how many scopes out the closest variable with this name is, and its slot there.
+    pub fn resolve(&self, name: &str) -> Option<(usize, usize)> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, locals)| Some((depth, locals.iter().position(|n| n == name)?)))
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Stops an Evaluator or TreeWalker from somewhere else, like another thread or a timer.
+/// Clones all share the same flag, so cancelling any of them cancels the one they came from.
+/// Once cancelled, it stays that way until it's reset, and every run is stopped as soon as it
+/// starts, so that a cancel can't be missed by a run that hadn't started yet.
+#[derive(Clone, Default, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Asks the Evaluator to stop before it runs its next instruction.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Lets runs go ahead again after being cancelled.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
@@ -1,21 +1,41 @@
-use super::{Parameters, Var};
+use super::{
+    number::{arithmetic, Operation},
+    Host, List, ListMode, Parameters, RuntimeError, Stdio, Var,
+};
 use crate::Raw;
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-/// This guys stores which variables are available in a given scope.
-/// Scopes have parents; if they don't know about a variable, they'll
-/// tell you to ask their parent about it. That's why parent is stored here.
+/// This guys stores the global variables, along with the standard functions programs can call.
+/// Variables local to a procedure call live in the call's scope instead, and only look here once
+/// they can't be found anywhere else.
+///
+/// Cloning one shares the standard functions rather than building them again, so a Context can
+/// be built once and cloned for every program that's run, even on other threads.
+#[derive(Clone)]
 pub struct Context {
     pub map: HashMap<String, Var>,
-    /// An index into the array of Contexts Evaluator stores.
+    /// An index into the array of Contexts Evaluator used to store, back when each scope was a
+    /// Context of its own. Nothing reads it anymore.
+    #[deprecated(note = "scopes aren't Contexts anymore, so a Context never has a parent")]
     pub parent: Option<usize>,
 }
 
 impl Context {
-    /// This Context is often used as the parent context that all other contexts spawn from.
+    /// This Context is what most programs are run in.
     /// STD stands for "standard" because this is the dictionary of standard functions.
+    /// DISPLAY prints to stdout, and INPUT reads from stdin.
     pub fn std() -> Self {
+        Self::with_host(Stdio)
+    }
+
+    /// The standard functions, with DISPLAY and INPUT going through the Host instead.
+    pub fn with_host(host: impl Host + 'static) -> Self {
         let mut map = HashMap::new();
+        let host = Arc::new(Mutex::new(host));
 
         macro_rules! insert_ops {
             ( $(
@@ -26,15 +46,15 @@ impl Context {
             )* ) => {
                 $(map.insert(
                     $op_symbol.to_string(),
-                    Var::Function(Box::new(|args: Parameters| {
+                    Var::native($op_symbol, |args| {
                         $( if let Ok(args) = args.$convert() {
-                            return Var::Raw(Raw::$type(args[0]$(.$postfix())? $op $($prefix)? args[1]));
+                            return Ok(Var::Raw(Raw::$type(args[0]$(.$postfix())? $op $($prefix)? args[1])));
                         };)+
-                        Var::Raw(Raw::Text(concat!(
+                        Err(RuntimeError::new(concat!(
                             "Can only apply the ", concat!( $( stringify!($op_name), " ", )* ),
                             "operation to ", operator_error!(first: $( $convert ,)+ ),
-                        ).to_string()))
-                    })),
+                        )))
+                    }),
                 );)*
             };
 
@@ -72,36 +92,101 @@ impl Context {
         map.insert("false".to_string(), Var::Raw(Raw::Bool(false)));
         map.insert(
             "DISPLAY".to_string(),
-            Var::Function(Box::new(|Parameters(args): Parameters| {
-                let output = args.iter().fold(String::new(), |acc, arg| {
-                    format!("{} {}", acc, arg).trim().to_owned()
-                });
-                print!("{}", output);
-                Var::Raw(Raw::Text(output))
-            })),
+            Var::native("DISPLAY", {
+                let host = host.clone();
+                move |Parameters(args, _)| {
+                    let output = args.iter().fold(String::new(), |acc, arg| {
+                        format!("{} {}", acc, arg).trim().to_owned()
+                    });
+                    host.lock().unwrap().output(&output);
+                    Ok(Var::Raw(Raw::Text(output)))
+                }
+            }),
+        );
+        // INPUT() or INPUT(prompt) gives back whatever text the Host comes up with.
+        map.insert(
+            "INPUT".to_string(),
+            Var::native("INPUT", move |Parameters(args, _)| {
+                let input = host.lock().unwrap().input(&input_prompt(&args)?)?;
+                Ok(Var::Raw(Raw::Text(input)))
+            }),
         );
 
         // format:
         /* Rust Version: long name("pseudo version", conversion function, output type) */
         insert_ops!(
-            ==: equals      ("=",   (numbers, Bool), (booleans, Bool), (strings, Bool))
-            +: add          ("+",   (numbers, Number), (strings, Text :clone |&))
-            -: subtract     ("-",   (numbers, Number))
-            /: divide       ("/",   (numbers, Number))
-            *: multiply     ("*",   (numbers, Number))
-            %: modulo       ("MOD", (numbers, Number))
-
-            >: less than    (">",   (numbers, Bool))
-            <: greater than ("<",   (numbers, Bool))
-
             &&: AND         ("AND", (booleans, Bool))
             ||: OR          ("OR",  (booleans, Bool))
         );
 
+        // numbers can be integers, decimals or reals, so arithmetic on them is done elsewhere.
+        for (symbol, name, operation) in [
+            ("+", "add", Operation::Add),
+            ("-", "subtract", Operation::Subtract),
+            ("/", "divide", Operation::Divide),
+            ("*", "multiply", Operation::Multiply),
+            ("MOD", "modulo", Operation::Modulo),
+        ] {
+            map.insert(
+                symbol.to_string(),
+                Var::native(symbol, move |Parameters(args, caller)| {
+                    if let [Var::Raw(a), Var::Raw(b)] = args.as_slice() {
+                        if let Some(answer) = arithmetic(operation, a, b, caller.number_mode()) {
+                            return answer.map(Var::Raw).map_err(RuntimeError::new);
+                        }
+                    }
+                    if operation == Operation::Add {
+                        if let [a, b] = args.as_slice() {
+                            if let (Ok(a), Ok(b)) = (a.string(), b.string()) {
+                                return Ok(Var::Raw(Raw::Text(a + &b)));
+                            }
+                        }
+                        return Err(RuntimeError::new(
+                            "Can only apply the add operation to numbers or strings!",
+                        ));
+                    }
+                    Err(RuntimeError::new(format!(
+                        "Can only apply the {} operation to numbers!",
+                        name
+                    )))
+                }),
+            );
+        }
+        for (symbol, wanted) in [(">", Ordering::Greater), ("<", Ordering::Less)] {
+            map.insert(
+                symbol.to_string(),
+                Var::native(symbol, move |Parameters(args, _)| match args.as_slice() {
+                    [a, b] => Ok(Var::Raw(Raw::Bool(a.order(b)? == Some(wanted)))),
+                    _ => Err(RuntimeError::new(format!(
+                        "{} compares two things!",
+                        symbol
+                    ))),
+                }),
+            );
+        }
+        map.insert("=".to_string(), Var::native("=", equals));
+
+        // these take a procedure, and call it on each of the items in a list.
+        map.insert("MAP".to_string(), Var::native("MAP", map_list));
+        map.insert("FILTER".to_string(), Var::native("FILTER", filter_list));
+        map.insert("REDUCE".to_string(), Var::native("REDUCE", reduce_list));
+
+        // the list procedures from the spec, which change the list they're given.
+        map.insert("APPEND".to_string(), Var::native("APPEND", append));
+        map.insert("INSERT".to_string(), Var::native("INSERT", insert));
+        map.insert("REMOVE".to_string(), Var::native("REMOVE", remove));
+        map.insert("LENGTH".to_string(), Var::native("LENGTH", length));
+
+        map.insert("RANDOM".to_string(), Var::native("RANDOM", random));
+        map.insert("BITS".to_string(), Var::native("BITS", bits));
+
+        #[allow(deprecated)]
         Self { map, parent: None }
     }
 
-    /// The Context a new scope starts with.
+    /// The Context a new scope used to start with.
+    #[deprecated(note = "scopes aren't Contexts anymore; local variables live in the call's scope")]
+    #[allow(deprecated)]
     pub fn empty_child(parent: usize) -> Self {
         Self {
             map: HashMap::new(),
@@ -109,3 +194,171 @@ impl Context {
         }
     }
 }
+
+/// What INPUT was asked to show, which is empty if it wasn't given anything.
+pub(crate) fn input_prompt(args: &[Var]) -> Result<String, RuntimeError> {
+    match args {
+        [] => Ok(String::new()),
+        [Var::Raw(Raw::Text(prompt))] => Ok(prompt.clone()),
+        [prompt] => Ok(prompt.to_string()),
+        _ => Err(RuntimeError::new("INPUT takes at most one prompt!")),
+    }
+}
+
+/// `a = b` works on any two values of the same kind, comparing lists item by item.
+fn equals(Parameters(args, _): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [a, b] => Ok(Var::Raw(Raw::Bool(a.equals(b)?))),
+        _ => Err(RuntimeError::new("= compares two things!")),
+    }
+}
+
+/// `RANDOM(a, b)` returns an integer from a to b, including both, which is equally likely to be
+/// any of them.
+fn random(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::Raw(Raw::Integer(low)), Var::Raw(Raw::Integer(high))] => {
+            if low > high {
+                return Err(RuntimeError::new(
+                    "RANDOM's first number can't be bigger than its second!",
+                ));
+            }
+            Ok(Var::Raw(Raw::Integer(caller.random(*low, *high)?)))
+        }
+        _ => Err(RuntimeError::new("RANDOM takes two integers!")),
+    }
+}
+
+/// `BITS(number)` returns the ones and zeros the number is stored as, with the Evaluator's
+/// NumberMode, so `BITS(5)` is 61 zeros and then `101`.
+fn bits(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::Raw(Raw::Decimal(_))] => Err(RuntimeError::new(
+            "BITS can't show an exact decimal, because it isn't stored in binary!",
+        )),
+        [Var::Raw(raw)] => match caller.number_mode().bit_pattern(raw) {
+            Some(pattern) => Ok(Var::Raw(Raw::Text(pattern))),
+            None => Err(RuntimeError::new("BITS takes a number!")),
+        },
+        _ => Err(RuntimeError::new("BITS takes a number!")),
+    }
+}
+
+/// What goes in a list when it's APPENDed or INSERTed, which is a copy if it's a list itself,
+/// unless lists are shared. Lists can't be put inside of themselves either way.
+fn item(list: &List, value: &Var, mode: ListMode) -> Result<Var, RuntimeError> {
+    match value {
+        Var::List(inside) if mode == ListMode::Reference && inside.holds(list) => {
+            Err(RuntimeError::new("Can't put a list inside of itself!"))
+        }
+        value if mode == ListMode::Copy => Ok(value.copied()),
+        value => Ok(value.clone()),
+    }
+}
+
+/// `APPEND(list, value)` adds the value to the end of the list, returning it.
+fn append(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), value] => {
+            let value = item(list, value, caller.list_mode())?;
+            list.modify(|items| items.push(value.clone()));
+            Ok(value)
+        }
+        _ => Err(RuntimeError::new("APPEND takes a list and a value!")),
+    }
+}
+
+/// `INSERT(list, i, value)` puts the value in the list at index i, counting from 1, moving the
+/// items from there on back by one. It can go anywhere from the start to the very end.
+fn insert(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), Var::Raw(Raw::Integer(i)), value] => {
+            let spots = list.len() + 1;
+            if *i < 1 || *i as u64 > spots as u64 {
+                return Err(RuntimeError::new(format!(
+                    "INSERT's index has to be from 1 to {}!",
+                    spots
+                )));
+            }
+            let value = item(list, value, caller.list_mode())?;
+            list.modify(|items| items.insert(*i as usize - 1, value.clone()));
+            Ok(value)
+        }
+        _ => Err(RuntimeError::new(
+            "INSERT takes a list, an integer index and a value!",
+        )),
+    }
+}
+
+/// `REMOVE(list, i)` takes the item at index i, counting from 1, out of the list, returning it.
+fn remove(Parameters(args, _): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), Var::Raw(Raw::Integer(i))] => {
+            let len = list.len();
+            if len == 0 {
+                return Err(RuntimeError::new("Can't REMOVE from an empty list!"));
+            }
+            if *i < 1 || *i as u64 > len as u64 {
+                return Err(RuntimeError::new(format!(
+                    "REMOVE's index has to be from 1 to {}!",
+                    len
+                )));
+            }
+            Ok(list.modify(|items| items.remove(*i as usize - 1)))
+        }
+        _ => Err(RuntimeError::new(
+            "REMOVE takes a list and an integer index!",
+        )),
+    }
+}
+
+/// `LENGTH(list)` is how many items are in the list.
+fn length(Parameters(args, _): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list)] => Ok(Var::Raw(Raw::Integer(list.len() as i64))),
+        _ => Err(RuntimeError::new("LENGTH takes a list!")),
+    }
+}
+
+/// `MAP(list, procedure)` returns a new list, holding what the procedure returns for each item.
+fn map_list(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), procedure] => (list.items().iter())
+            .map(|item| caller.call(procedure, vec![item.clone()]))
+            .collect::<Result<_, _>>()
+            .map(Var::list),
+        _ => Err(RuntimeError::new("MAP takes a list and a procedure!")),
+    }
+}
+
+/// `FILTER(list, procedure)` returns a new list, holding only the items the procedure
+/// returns true for.
+fn filter_list(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), procedure] => {
+            let mut kept = Vec::new();
+            for item in list.items().iter() {
+                if caller.call(procedure, vec![item.clone()])?.boolean()? {
+                    kept.push(item.clone());
+                }
+            }
+            Ok(Var::list(kept))
+        }
+        _ => Err(RuntimeError::new("FILTER takes a list and a procedure!")),
+    }
+}
+
+/// `REDUCE(list, procedure, start)` calls the procedure with `start` and the first item, then
+/// with what that returned and the second item, and so on, returning whatever it returns last.
+fn reduce_list(Parameters(args, caller): Parameters) -> Result<Var, RuntimeError> {
+    match args.as_slice() {
+        [Var::List(list), procedure, start] => {
+            list.items().iter().try_fold(start.clone(), |acc, item| {
+                caller.call(procedure, vec![acc, item.clone()])
+            })
+        }
+        _ => Err(RuntimeError::new(
+            "REDUCE takes a list, a procedure and a starting value!",
+        )),
+    }
+}
@@ -0,0 +1,376 @@
+use super::{
+    replay::{quote, unquote},
+    Context, Evaluator, Frame, Random, RuntimeError, Statements, Var,
+};
+use crate::{ast::Ast, compile::compile};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt::Write,
+    sync::Arc,
+};
+
+/// Runs a program on an Evaluator a statement at a time, stopping wherever it's asked to so that
+/// the program can be looked at in the middle of running.
+/// Procedures that natives call, like the ones passed to MAP, are run without stopping.
+///
+/// The start of every statement it runs is remembered, even the ones it doesn't stop at, so that
+/// it can go back to any of them and pick up from there. Only the last HISTORY_LIMIT of them are
+/// kept, unless the host says otherwise, so that a long loop doesn't use up all of the memory.
+/// Going back undoes the changes made to variables and forgets what was DISPLAYed since, but
+/// anything the program did outside of the Evaluator, like printing, happens again when it's run
+/// forward from there.
+pub struct Debugger {
+    evaluator: Evaluator,
+    breakpoints: BTreeSet<u32>,
+    /// The line each Frame is on, which is how new statements are noticed.
+    lines: Statements,
+    /// What the program ended with, once it has.
+    done: Option<Result<Var, RuntimeError>>,
+    /// The start of each statement that's been run, oldest first.
+    history: VecDeque<Moment>,
+    /// How many statements are remembered before the oldest ones are forgotten.
+    history_limit: usize,
+    /// How many changes to variables have been forgotten along with the oldest statements.
+    forgotten: usize,
+}
+
+/// Everything needed to go back to the start of a statement, besides the changes made to
+/// variables since, which the Evaluator keeps track of.
+struct Moment {
+    frames: Vec<Frame>,
+    stack: Vec<Var>,
+    lines: Statements,
+    steps: u64,
+    /// Where RANDOM was, so that it picks the same numbers when the program is run forward again.
+    random: Random,
+    /// How many changes had been made to variables, counting the ones that have been forgotten.
+    writes: usize,
+    /// How many things had been DISPLAYed.
+    output: usize,
+}
+
+/// Where the program is after the Debugger has run it for a bit.
+pub enum Status {
+    /// Stopped before running the statement on this line.
+    Paused(u32),
+    /// The program is over, and this is the value it ended with.
+    Finished(Var),
+}
+
+/// A procedure or lambda call, as it is while the program is paused.
+pub struct StackFrame {
+    /// None for the program itself, rather than a procedure or lambda it called.
+    pub procedure: Option<String>,
+    /// The line the call is on. For all but the innermost call, it's where it called the next one.
+    pub line: u32,
+    /// Every variable that the code in the call can see, by name. The call's own variables come
+    /// first, then those of the procedures it was defined in, and then the globals.
+    pub variables: Vec<(String, Var)>,
+}
+
+/// How many statements the Debugger remembers, unless the host says otherwise.
+pub const HISTORY_LIMIT: usize = 10_000;
+
+/// How far the Debugger runs before stopping on its own, when it doesn't run into a breakpoint.
+#[derive(Clone, Copy)]
+enum Step {
+    /// Until the next statement, wherever it is.
+    Into,
+    /// Until the next statement that isn't inside of a call made by this one.
+    Over,
+    /// Until the next statement after this call returns.
+    Out,
+    /// Only breakpoints stop it.
+    Run,
+}
+
+impl Debugger {
+    /// Gets the AST ready to run on the Evaluator, paused before its first statement.
+    pub fn new(mut evaluator: Evaluator, ast: Ast) -> Result<Self, RuntimeError> {
+        evaluator.start(Arc::new(compile(&ast)?));
+        Ok(Self::watch(evaluator, Vec::new(), Statements::default()))
+    }
+
+    /// Picks up a program from a Debugger's `snapshot`, stopped where it was and with what it had
+    /// DISPLAYed, on a new Evaluator with this Context. There's nowhere to go back to from there.
+    pub fn restore(snapshot: &str, context: Context) -> Result<Self, String> {
+        let broken = || "the snapshot doesn't have what the Debugger needs at the end".to_string();
+        let (snapshot, lines) = (snapshot.trim_end().rsplit_once('\n')).ok_or_else(broken)?;
+        let (snapshot, output) = snapshot.rsplit_once('\n').ok_or_else(broken)?;
+
+        let mut words = lines.split_whitespace();
+        if words.next() != Some("lines") {
+            return Err(broken());
+        }
+        let lines = words.map(str::parse).collect::<Result<Vec<u32>, _>>();
+        let lines = lines.map_err(|_| broken())?;
+        let mut text = output
+            .strip_prefix("output")
+            .ok_or_else(broken)?
+            .trim_start();
+        let mut output = Vec::new();
+        while let Some((line, rest)) = unquote(text) {
+            output.push(line);
+            text = rest.trim_start();
+        }
+        if !text.is_empty() {
+            return Err(broken());
+        }
+
+        let evaluator = Evaluator::restore(snapshot, context)?;
+        if evaluator.frames.is_empty() || evaluator.waiting.is_some() {
+            return Err("a Debugger can only pick up a program that's stopped partway".to_string());
+        }
+        if lines.len() > evaluator.frames.len() {
+            return Err(broken());
+        }
+        let started = !lines.is_empty();
+        let mut debugger = Self::watch(evaluator, output, Statements(lines));
+        // the statement it's stopped at is the one it can go back to.
+        if started {
+            debugger.history.push_back(Moment {
+                frames: debugger.evaluator.frames.clone(),
+                stack: debugger.evaluator.stack.clone(),
+                lines: debugger.lines.clone(),
+                steps: debugger.evaluator.steps,
+                random: debugger.evaluator.random.clone(),
+                writes: 0,
+                output: debugger.output_len(),
+            });
+        }
+        Ok(debugger)
+    }
+
+    /// Writes down the program where it's stopped, along with everything it's DISPLAYed so far,
+    /// for `restore` to pick up from. It's the same as the Evaluator's `snapshot`, with a couple
+    /// of lines on the end, but where the program has been isn't written down.
+    pub fn snapshot(&self) -> Result<String, String> {
+        if self.done.is_some() {
+            return Err("the program is already over".to_string());
+        }
+        let mut out = self.evaluator.snapshot()?;
+        out += "output";
+        for line in self.evaluator.displayed.iter().flatten() {
+            write!(out, " {}", quote(line)).unwrap();
+        }
+        out += "\nlines";
+        for line in &self.lines.0 {
+            write!(out, " {}", line).unwrap();
+        }
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// Starts keeping track of the Evaluator's program, which is ready to run, and of everything
+    /// it DISPLAYs after what it already has.
+    fn watch(mut evaluator: Evaluator, output: Vec<String>, lines: Statements) -> Self {
+        evaluator.journal = Some(Vec::new());
+        evaluator.displayed = Some(output);
+        Self {
+            evaluator,
+            breakpoints: BTreeSet::new(),
+            lines,
+            done: None,
+            history: VecDeque::new(),
+            history_limit: HISTORY_LIMIT,
+            forgotten: 0,
+        }
+    }
+
+    /// Changes how many statements are remembered for going back to. If there are more than that
+    /// already, the oldest ones are forgotten. It has to be at least 1, for the statement the
+    /// program is stopped at.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit.max(1);
+        while self.history.len() > self.history_limit {
+            forget_oldest(&mut self.history, &mut self.evaluator, &mut self.forgotten);
+        }
+    }
+
+    /// Makes the program stop before running any statement on that line.
+    pub fn set_breakpoint(&mut self, line: u32) {
+        self.breakpoints.insert(line);
+    }
+
+    pub fn clear_breakpoint(&mut self, line: u32) {
+        self.breakpoints.remove(&line);
+    }
+
+    /// The lines the program will stop on, in order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u32> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Runs the next statement, stopping at the start of the first procedure it calls.
+    pub fn step_into(&mut self) -> Result<Status, RuntimeError> {
+        self.go(Step::Into)
+    }
+
+    /// Runs the next statement, along with any procedures it calls.
+    pub fn step_over(&mut self) -> Result<Status, RuntimeError> {
+        self.go(Step::Over)
+    }
+
+    /// Runs until the procedure that's being run returns to whatever called it.
+    pub fn step_out(&mut self) -> Result<Status, RuntimeError> {
+        self.go(Step::Out)
+    }
+
+    /// Runs until the program gets to a breakpoint, or ends.
+    pub fn run_to_breakpoint(&mut self) -> Result<Status, RuntimeError> {
+        self.go(Step::Run)
+    }
+
+    /// Goes back to the start of the statement before the one it's stopped at, returning its line.
+    /// Once the program is over, it goes back to the start of the last statement. If there's
+    /// nowhere to go back to, nothing happens and None is returned.
+    pub fn step_back(&mut self) -> Option<u32> {
+        let back = match self.done {
+            Some(_) => self.history.len().checked_sub(1)?,
+            None => self.history.len().checked_sub(2)?,
+        };
+        self.rewind(back)
+    }
+
+    /// Goes back to the start of one of the statements that's been run, counting from 0 for the
+    /// oldest one that's remembered, returning its line. Everything that's been run after that is
+    /// forgotten.
+    pub fn rewind(&mut self, to: usize) -> Option<u32> {
+        self.history.truncate(to + 1);
+        let moment = self.history.get(to)?;
+
+        let journal = self.evaluator.journal.as_mut().unwrap();
+        let writes = journal.split_off(moment.writes - self.forgotten);
+        for write in writes.into_iter().rev() {
+            self.evaluator.undo(write);
+        }
+        self.evaluator.frames = moment.frames.clone();
+        self.evaluator.stack = moment.stack.clone();
+        self.evaluator.steps = moment.steps;
+        self.evaluator.random = moment.random.clone();
+        if let Some(displayed) = &mut self.evaluator.displayed {
+            displayed.truncate(moment.output);
+        }
+        self.lines = moment.lines.clone();
+        self.done = None;
+        self.lines.0.last().copied()
+    }
+
+    /// How many statements are remembered, including the one it's stopped at.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Everything the program has DISPLAYed so far, one call to a line.
+    pub fn output(&self) -> Vec<String> {
+        self.evaluator.displayed.clone().unwrap_or_default()
+    }
+
+    fn output_len(&self) -> usize {
+        self.evaluator.displayed.as_ref().map_or(0, Vec::len)
+    }
+
+    /// The calls that are being run, innermost first, ending with the program itself.
+    /// This is empty before the first statement has been paused at and after the program ends.
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        if self.done.is_some() {
+            return Vec::new();
+        }
+        let frames = self.evaluator.frames.iter().zip(&self.lines.0).enumerate();
+        frames
+            .rev()
+            .map(|(i, (frame, &line))| StackFrame {
+                procedure: (i > 0)
+                    .then(|| frame.chunk.name.as_deref().unwrap_or("<lambda>").into()),
+                line,
+                variables: self.evaluator.variables(frame.scope.as_ref()),
+            })
+            .collect()
+    }
+
+    /// The Evaluator the program is running on, for looking at its globals.
+    pub fn evaluator(&self) -> &Evaluator {
+        &self.evaluator
+    }
+
+    /// Gives back the Evaluator, with whatever globals the program has made so far.
+    pub fn into_evaluator(mut self) -> Evaluator {
+        self.evaluator.journal = None;
+        self.evaluator.displayed = None;
+        self.evaluator
+    }
+
+    /// Runs until a new statement starts somewhere the step says to stop, or on a breakpoint.
+    fn go(&mut self, step: Step) -> Result<Status, RuntimeError> {
+        if let Some(done) = &self.done {
+            return done.clone().map(Status::Finished);
+        }
+
+        let Self {
+            evaluator,
+            breakpoints,
+            lines,
+            history,
+            history_limit,
+            forgotten,
+            ..
+        } = self;
+        // before the first statement, the program itself is where it's stepping from.
+        let from = lines.0.len().max(1);
+        let mut pause = |evaluator: &mut Evaluator| {
+            let line = match lines.advance(&evaluator.frames, |_| {}) {
+                Some(line) => line,
+                None => return false,
+            };
+            let depth = evaluator.frames.len();
+
+            history.push_back(Moment {
+                frames: evaluator.frames.clone(),
+                stack: evaluator.stack.clone(),
+                lines: lines.clone(),
+                steps: evaluator.steps,
+                random: evaluator.random.clone(),
+                writes: *forgotten + evaluator.journal.as_ref().map_or(0, Vec::len),
+                output: evaluator.displayed.as_ref().map_or(0, Vec::len),
+            });
+            // the oldest statement is forgotten as soon as there's one too many, so that running
+            // a long loop doesn't remember any more than stepping through it would.
+            if history.len() > *history_limit {
+                forget_oldest(history, evaluator, forgotten);
+            }
+            breakpoints.contains(&line)
+                || match step {
+                    Step::Into => true,
+                    Step::Over => depth <= from,
+                    Step::Out => depth < from,
+                    Step::Run => false,
+                }
+        };
+
+        let result = evaluator.proceed(0, Some(&mut pause));
+        match result {
+            Ok(None) => Ok(Status::Paused(*self.lines.0.last().unwrap())),
+            done => {
+                let done = done.map(Option::unwrap);
+                self.done = Some(done.clone());
+                done.map(Status::Finished)
+            }
+        }
+    }
+}
+
+/// Forgets the oldest statement that's remembered, along with the changes to variables that only
+/// going back to it would undo. Those are let go of in batches, rather than one statement at a
+/// time, so that forgetting doesn't take longer the more there is to remember.
+fn forget_oldest(history: &mut VecDeque<Moment>, evaluator: &mut Evaluator, forgotten: &mut usize) {
+    history.pop_front();
+    let oldest = match history.front() {
+        Some(moment) => moment.writes - *forgotten,
+        None => return,
+    };
+    let journal = evaluator.journal.as_mut().unwrap();
+    if oldest > journal.len() / 2 {
+        journal.drain(..oldest);
+        *forgotten += oldest;
+    }
+}
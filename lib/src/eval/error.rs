@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Something that went wrong while a program was running, which stops it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    /// The line of source code that was running when it happened. Natives don't know where
+    /// they were called from, so this is filled in for them once the error gets back to the
+    /// Evaluator.
+    pub line: Option<u32>,
+    /// The procedure calls that were still going when it happened, innermost first.
+    /// The last one is always the program itself.
+    pub trace: Vec<TraceFrame>,
+}
+
+/// A procedure call that was in the middle of running when an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// None for the program itself, rather than a procedure or lambda it called.
+    pub procedure: Option<String>,
+    /// The line that call had gotten to, which for all but the innermost call is where it called
+    /// the next one.
+    pub line: u32,
+}
+impl RuntimeError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Self {
+            message: message.into(),
+            line: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Says that the error happened on the given line, unless it's already known where it did.
+    pub(crate) fn at(mut self, line: u32) -> Self {
+        self.line.get_or_insert(line);
+        self
+    }
+
+    /// Gives the error a stack trace, unless it already has one from further in.
+    pub(crate) fn traced(mut self, trace: impl FnOnce() -> Vec<TraceFrame>) -> Self {
+        if self.trace.is_empty() {
+            self.trace = trace();
+        }
+        self
+    }
+}
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+/// Formatting with `{:#}` adds the stack trace after the message, one call to a line.
+/// Calls that are just like the one before them, as they are when a procedure recurses until it
+/// hits the recursion limit, go on the same line, with how many of them there were.
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        if f.alternate() {
+            for frames in self.trace.chunk_by(|a, b| a == b) {
+                write!(f, "\n    {}", frames[0])?;
+                if frames.len() > 1 {
+                    write!(f, " ×{}", frames.len())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.procedure {
+            Some(name) => write!(f, "at line {} in {}", self.line, name),
+            None => write!(f, "at line {} in the program", self.line),
+        }
+    }
+}
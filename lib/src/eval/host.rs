@@ -0,0 +1,103 @@
+use super::RuntimeError;
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Write},
+    sync::{Arc, Mutex},
+};
+
+/// Wherever a program's DISPLAYs go, and its INPUTs come from.
+/// Hand one to `Context::with_host` to get the standard functions talking to it.
+/// The standard functions can be called from any thread, so the Host has to be able to move there.
+pub trait Host: Send {
+    /// Shows what the program DISPLAYed. The arguments have already been joined up with spaces.
+    fn output(&mut self, text: &str);
+
+    /// Asks for a line of input, without the newline at the end.
+    fn input(&mut self, prompt: &str) -> Result<String, RuntimeError>;
+}
+
+/// Prints to stdout, and reads lines from stdin.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stdio;
+
+impl Host for Stdio {
+    fn output(&mut self, text: &str) {
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    }
+
+    fn input(&mut self, prompt: &str) -> Result<String, RuntimeError> {
+        print!("{}", prompt);
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) => Err(RuntimeError::new("there's no more input to read")),
+            Ok(_) => Ok(line.trim_end_matches(&['\n', '\r'][..]).to_string()),
+            Err(e) => Err(RuntimeError::new(format!("couldn't read input: {}", e))),
+        }
+    }
+}
+
+/// Keeps everything that's DISPLAYed in memory, one call to an item.
+/// Clones all share the same output, so one can be kept around to look at it once the Context
+/// has the other. It doesn't have any input to give.
+#[derive(Clone, Default, Debug)]
+pub struct Capture(Arc<Mutex<Vec<String>>>);
+
+impl Capture {
+    pub fn output(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Forgets everything that's been DISPLAYed so far.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Host for Capture {
+    fn output(&mut self, text: &str) {
+        self.0.lock().unwrap().push(text.to_string());
+    }
+
+    fn input(&mut self, _prompt: &str) -> Result<String, RuntimeError> {
+        Err(RuntimeError::new("there's no input to read"))
+    }
+}
+
+/// Gives out a list of inputs decided ahead of time, one each time INPUT is called, and keeps
+/// what's DISPLAYed in a Capture.
+#[derive(Clone, Default, Debug)]
+pub struct Scripted {
+    inputs: VecDeque<String>,
+    pub output: Capture,
+}
+
+impl Scripted {
+    pub fn new<I, S>(inputs: I, output: Capture) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            inputs: inputs.into_iter().map(Into::into).collect(),
+            output,
+        }
+    }
+
+    /// The inputs that haven't been given out yet.
+    pub fn remaining(&self) -> impl Iterator<Item = &str> {
+        self.inputs.iter().map(String::as_str)
+    }
+}
+
+impl Host for Scripted {
+    fn output(&mut self, text: &str) {
+        Host::output(&mut self.output, text);
+    }
+
+    fn input(&mut self, _prompt: &str) -> Result<String, RuntimeError> {
+        (self.inputs.pop_front()).ok_or_else(|| RuntimeError::new("ran out of scripted input"))
+    }
+}
@@ -0,0 +1,160 @@
+use super::Var;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// What giving a variable a list does, which the host picks for the Evaluator or TreeWalker.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ListMode {
+    /// `b <- a` gives b a copy of the list in a, like the spec's reference sheet says, so that
+    /// adding to one of them doesn't change the other. So does putting a list inside another.
+    #[default]
+    Copy,
+    /// `b <- a` makes b another name for the list in a, like it is in Python or JavaScript, so
+    /// that adding to either of them changes both.
+    Reference,
+}
+
+/// A list of values. Cloning one gives another handle to the very same list, which is how lists
+/// are passed to procedures, so that it's cheap, and so that procedures can change the list
+/// they're given.
+///
+/// Copies share their items with the list they were copied from until one of them is changed,
+/// which is when the items are copied for real. That way a list can be given to any number of
+/// variables without it taking any longer than giving them a number.
+#[derive(Clone)]
+pub struct List(Arc<Mutex<Store>>);
+
+struct Store {
+    items: Arc<Vec<Var>>,
+    /// Whether the lists inside might still be shared with a copy. A list doesn't give itself
+    /// its own copies of them until something could change them, so copying a list of lists is
+    /// as quick as copying any other list.
+    nested_shared: bool,
+    /// How many times the list has been changed, so that it's easy to tell if it was.
+    changes: u64,
+}
+
+impl Store {
+    /// Gives the list its own copies of the lists inside it, if it might not have them yet.
+    fn own_nested(&mut self) {
+        if std::mem::take(&mut self.nested_shared)
+            && self.items.iter().any(|item| matches!(item, Var::List(_)))
+        {
+            self.items = Arc::new(self.items.iter().map(Var::copied).collect());
+        }
+    }
+}
+
+/// What was in a list at some point, for putting it back that way.
+#[derive(Clone)]
+pub(super) struct Saved(pub(super) Arc<Vec<Var>>, pub(super) bool);
+
+impl List {
+    pub fn new(items: Vec<Var>) -> Self {
+        Self::from_store(Arc::new(items), false)
+    }
+
+    fn from_store(items: Arc<Vec<Var>>, nested_shared: bool) -> Self {
+        Self(Arc::new(Mutex::new(Store {
+            items,
+            nested_shared,
+            changes: 0,
+        })))
+    }
+
+    /// The items, as they are now. Changing the list afterwards doesn't change these, so
+    /// procedures can be called on each of them without worrying about what they do to it.
+    pub fn items(&self) -> Arc<Vec<Var>> {
+        let mut store = self.0.lock().unwrap();
+        store.own_nested();
+        store.items.clone()
+    }
+
+    /// The items, for looking at without changing the lists inside them. Unlike `items`, the
+    /// lists inside might be shared with a copy of this list.
+    pub(super) fn peek(&self) -> Arc<Vec<Var>> {
+        self.0.lock().unwrap().items.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Changes the items, copying them first if a copy of the list is still sharing them.
+    pub fn modify<T>(&self, change: impl FnOnce(&mut Vec<Var>) -> T) -> T {
+        let mut store = self.0.lock().unwrap();
+        store.own_nested();
+        store.changes += 1;
+        change(Arc::make_mut(&mut store.items))
+    }
+
+    /// What's in the list now, for `set` to put back later.
+    pub(super) fn save(&self) -> Saved {
+        let store = self.0.lock().unwrap();
+        Saved(store.items.clone(), store.nested_shared)
+    }
+
+    /// Puts back what `save` saved, for undoing changes.
+    pub(super) fn set(&self, Saved(items, nested_shared): Saved) {
+        let mut store = self.0.lock().unwrap();
+        store.items = items;
+        store.nested_shared = nested_shared;
+        store.changes += 1;
+    }
+
+    /// How many times the list has been changed.
+    pub(super) fn changes(&self) -> u64 {
+        self.0.lock().unwrap().changes
+    }
+
+    /// A new list with the same items, that doesn't change when this one does, or the other way
+    /// around. The lists inside of it aren't copied until one of the two lists is changed, or
+    /// gives them out, and even then, only their handles are, and not their items.
+    pub fn copy(&self) -> List {
+        let mut store = self.0.lock().unwrap();
+        store.nested_shared = true;
+        Self::from_store(store.items.clone(), true)
+    }
+
+    /// Whether both are handles to the same list, rather than to lists that just look alike.
+    pub fn same(&self, other: &List) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Whether the list is the other one, or has it inside of it somewhere, for procedures that
+    /// put things in lists to check that they aren't putting a list inside of itself. Each list
+    /// is only looked inside of once, in case a host has put one inside of itself already.
+    pub fn holds(&self, other: &List) -> bool {
+        let mut seen = HashSet::new();
+        let mut todo = vec![self.clone()];
+        while let Some(list) = todo.pop() {
+            if list.same(other) {
+                return true;
+            }
+            if seen.insert(list.id()) {
+                todo.extend(list.peek().iter().filter_map(|item| match item {
+                    Var::List(inside) => Some(inside.clone()),
+                    _ => None,
+                }));
+            }
+        }
+        false
+    }
+
+    /// Something that's the same for every list sharing these items, until they're changed.
+    pub(super) fn items_id(&self) -> *const Vec<Var> {
+        Arc::as_ptr(&self.0.lock().unwrap().items)
+    }
+
+    /// Something that's the same for every handle to this list, and different for every other one
+    /// that's around at the same time.
+    pub(super) fn id(&self) -> *const () {
+        Arc::as_ptr(&self.0) as *const ()
+    }
+}
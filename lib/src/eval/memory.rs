@@ -0,0 +1,207 @@
+use super::{Evaluator, List, RuntimeError, Scope, Var};
+use crate::Raw;
+use std::{collections::HashSet, fmt, mem::size_of, sync::Arc};
+
+/// How much memory a program can use. None means there's no limit, which is the default for all
+/// of them.
+/// Lists and bytes are counted across every value the program is holding on to at once, so that
+/// lots of small lists can't get around the limit any more than one big one can. Copies of a list
+/// share its items until one of them is changed, so they're only counted once until then.
+/// Text is limited one piece at a time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryLimits {
+    /// How many items all of the lists can hold between them.
+    pub list_items: Option<usize>,
+    /// How many characters long a piece of text can be.
+    pub text_length: Option<usize>,
+    /// Roughly how many bytes the lists and text can take up between them.
+    pub bytes: Option<usize>,
+}
+
+/// How much memory a program is using, or the most it used at once while it ran.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub list_items: usize,
+    /// The length of the longest text.
+    pub text_length: usize,
+    pub bytes: usize,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} list items, {} characters of text and about {} bytes",
+            self.list_items, self.text_length, self.bytes
+        )
+    }
+}
+
+impl Usage {
+    /// What making the value took, not counting the values inside of it, which were counted when
+    /// they were made.
+    fn made(var: &Var) -> Option<Self> {
+        match var {
+            Var::Raw(Raw::Text(text)) => Some(Usage {
+                list_items: 0,
+                text_length: text.chars().count(),
+                bytes: text.len(),
+            }),
+            Var::List(list) => Some(Usage::items(list.len())),
+            _ => None,
+        }
+    }
+
+    fn items(count: usize) -> Self {
+        Usage {
+            list_items: count,
+            text_length: 0,
+            bytes: count * size_of::<Var>(),
+        }
+    }
+
+    fn max(self, other: Usage) -> Usage {
+        Usage {
+            list_items: self.list_items.max(other.list_items),
+            text_length: self.text_length.max(other.text_length),
+            bytes: self.bytes.max(other.bytes),
+        }
+    }
+}
+
+/// Adds up the memory taken by everything a program can still get at, counting each list and
+/// Scope once however many things share it.
+#[derive(Default)]
+struct Tally {
+    usage: Usage,
+    lists: HashSet<*const Vec<Var>>,
+    scopes: HashSet<*const Scope>,
+    /// What's been found but not looked inside of yet, so that deeply nested lists don't need
+    /// the Rust stack to be deep as well.
+    todo: Vec<Var>,
+}
+
+impl Tally {
+    /// Adds everything that's been found so far, and whatever can be gotten at from it.
+    fn total(mut self) -> Usage {
+        while let Some(var) = self.todo.pop() {
+            match var {
+                Var::Raw(Raw::Text(text)) => self.usage.bytes += text.len(),
+                Var::List(list) => {
+                    let items = list.peek();
+                    if self.lists.insert(Arc::as_ptr(&items)) {
+                        let made = Usage::items(items.len());
+                        self.usage.list_items += made.list_items;
+                        self.usage.bytes += made.bytes;
+                        self.todo.extend(items.iter().cloned());
+                    }
+                }
+                Var::Compiled(_, Some(scope)) => self.scope(&scope),
+                _ => {}
+            }
+        }
+        self.usage
+    }
+
+    /// Finds the variables in a Scope, and in the Scopes around it.
+    fn scope(&mut self, mut scope: &Arc<Scope>) {
+        while self.scopes.insert(Arc::as_ptr(scope)) {
+            let slots = scope.slots.lock().unwrap();
+            self.todo.extend(slots.iter().flatten().cloned());
+            drop(slots);
+            match &scope.parent {
+                Some(parent) => scope = parent,
+                None => break,
+            }
+        }
+    }
+}
+
+impl Evaluator {
+    /// Stops programs that make values bigger than this, so that they can't run the machine out
+    /// of memory.
+    pub fn set_memory_limits(&mut self, limits: MemoryLimits) {
+        self.memory_limits = limits;
+    }
+
+    /// The most memory the last run was using at once, or the current one so far, counting the
+    /// globals it started with. It's only kept track of while there are memory limits.
+    pub fn memory_peak(&self) -> Usage {
+        self.memory_peak
+    }
+
+    /// Starts keeping track of memory for a new run, from what the Evaluator is already holding.
+    pub(super) fn start_measuring(&mut self) {
+        self.memory_used = Usage::default();
+        if self.memory_limits != MemoryLimits::default() {
+            self.memory_used = self.memory_in_use(None);
+        }
+        self.memory_peak = self.memory_used;
+    }
+
+    /// Counts a value that was just made toward the limits.
+    pub(super) fn measure(&mut self, var: &Var) -> Result<(), RuntimeError> {
+        match Usage::made(var) {
+            Some(made) => self.count(made, var),
+            None => Ok(()),
+        }
+    }
+
+    /// Counts items that a native made room for in a list toward the limits.
+    pub(super) fn measure_items(&mut self, list: &List, made: usize) -> Result<(), RuntimeError> {
+        self.count(Usage::items(made), &Var::List(list.clone()))
+    }
+
+    /// Adds what was made to the running total, and makes sure it's within the limits. The value
+    /// it was made for might not be anywhere the program can get at yet, so it's counted too.
+    /// What's been thrown away since is only taken off once the total looks like it's over,
+    /// when everything that's left is added up all over again. That only happens once the
+    /// program has made about as much again as it was using, so it doesn't take long on average.
+    fn count(&mut self, made: Usage, var: &Var) -> Result<(), RuntimeError> {
+        let limits = self.memory_limits;
+        if limits == MemoryLimits::default() {
+            return Ok(());
+        }
+        let over = |limit: Option<usize>, used: usize| limit.filter(|&limit| used > limit);
+        let over_total = |used: Usage| {
+            over(limits.list_items, used.list_items).is_some()
+                || over(limits.bytes, used.bytes).is_some()
+        };
+        self.memory_used.list_items += made.list_items;
+        self.memory_used.bytes += made.bytes;
+        if over_total(self.memory_used) {
+            self.memory_used = self.memory_in_use(Some(var));
+        }
+        self.memory_peak = (self.memory_peak).max(self.memory_used).max(Usage {
+            text_length: made.text_length,
+            ..Usage::default()
+        });
+
+        let used = self.memory_used;
+        let exceeded = if let Some(limit) = over(limits.list_items, used.list_items) {
+            format!("lists can only hold {} items in all", limit)
+        } else if let Some(limit) = over(limits.text_length, made.text_length) {
+            format!("text can only be {} characters long", limit)
+        } else if let Some(limit) = over(limits.bytes, used.bytes) {
+            format!("values can only take up about {} bytes in all", limit)
+        } else {
+            return Ok(());
+        };
+        Err(RuntimeError::new(format!(
+            "memory limit exceeded: {} (the peak was {})",
+            exceeded, self.memory_peak
+        )))
+    }
+
+    /// Adds up the memory taken by the globals, what's on the stack, the Scopes of the calls
+    /// that are going, and the value that was just made, if there is one.
+    fn memory_in_use(&self, made: Option<&Var>) -> Usage {
+        let mut tally = Tally::default();
+        let vars = self.context.map.values().chain(&self.stack).chain(made);
+        tally.todo.extend(vars.cloned());
+        for scope in self.frames.iter().filter_map(|frame| frame.scope.as_ref()) {
+            tally.scope(scope);
+        }
+        tally.total()
+    }
+}
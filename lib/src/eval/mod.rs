@@ -1,159 +1,946 @@
 mod var;
-pub use var::Var;
+pub use var::{Native, Var};
+
+mod error;
+pub use error::{RuntimeError, TraceFrame};
 
 mod param;
-pub use param::Parameters;
+pub use param::{Call, Parameters};
+
+mod cancel;
+pub use cancel::CancelHandle;
+
+mod random;
+pub use random::Random;
+
+mod list;
+use list::Saved;
+pub use list::{List, ListMode};
+
+mod number;
+pub use number::{Decimal, NumberMode, Overflow, Precision};
+
+mod memory;
+pub use memory::{MemoryLimits, Usage};
+
+mod debug;
+pub use debug::{Debugger, StackFrame, Status, HISTORY_LIMIT};
+
+mod trace;
+pub use trace::{TraceRow, TraceTable};
+
+mod observe;
+pub use observe::Observer;
+
+mod suspend;
+pub use suspend::Progress;
+
+mod replay;
+use replay::Tape;
+pub use replay::{Event, Replay};
+
+mod snapshot;
+
+mod host;
+pub use host::{Capture, Host, Scripted, Stdio};
 
 mod context;
 pub use context::Context;
 
+mod walk;
+pub use walk::TreeWalker;
+
 use super::{
-    ast::{Ast, Node},
+    ast::Ast,
+    compile::{compile, Chunk, Op, Slot},
     Raw,
 };
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
 /// An Evaluator evalutes source code and stores the Context that
 /// source code is run inside of an manipulates/stores variables in.
+/// Source code is compiled to bytecode, which the Evaluator runs on a stack machine.
 pub struct Evaluator {
-    contexts: Vec<Context>,
+    /// Where globals are kept, along with everything the host provides.
+    context: Context,
+    stack: Vec<Var>,
+    /// The program, and every procedure or lambda it's in the middle of calling.
+    frames: Vec<Frame>,
+    /// How many calls can be going at once before the program is stopped.
+    recursion_limit: usize,
+    /// How much of the Rust stack the calls natives are making use up.
+    stack_depth: StackDepth,
+    /// How many instructions a run can take before it's stopped, if there's a limit at all.
+    step_limit: Option<u64>,
+    memory_limits: MemoryLimits,
+    /// Roughly how much memory the program is using, counting what it's made since it was last
+    /// added up even if it's been thrown away.
+    memory_used: Usage,
+    /// The most memory the current run has used at once.
+    memory_peak: Usage,
+    /// How many instructions the current run has taken so far.
+    steps: u64,
+    cancel: CancelHandle,
+    random: Random,
+    number_mode: NumberMode,
+    /// The constants of the Chunks the current run has used, worked out for the NumberMode, along
+    /// with the Chunks themselves so that their addresses aren't used by any others.
+    literals: HashMap<usize, (Arc<Chunk>, Literals)>,
+    list_mode: ListMode,
+    /// The Replay being recorded or followed, if there is one.
+    tape: Option<Tape>,
+    /// Every change made to a variable, oldest first, if something wants to be able to undo them.
+    journal: Option<Vec<Write>>,
+    /// Everything the program has DISPLAYed, if something wants to keep track of it.
+    displayed: Option<Vec<String>>,
+    observer: Option<Box<dyn Observer>>,
+    /// The statements the Observer has been told have started, but not that they've ended.
+    statements: Statements,
+    /// Whether calling INPUT should stop the program, rather than asking the Host.
+    resumable: bool,
+    /// The prompt of the INPUT the program stopped at, until it's resumed.
+    waiting: Option<String>,
+}
+
+/// A Chunk's constants, made into what a NumberMode says they should be.
+type Literals = Arc<[Result<Raw, String>]>;
+
+/// Looks at the Evaluator before each instruction it runs, and says whether to stop before it.
+type Pause<'a> = &'a mut dyn FnMut(&mut Evaluator) -> bool;
+
+/// How many calls to procedures and lambdas can be going at once, unless the host says otherwise.
+pub const RECURSION_LIMIT: usize = 1000;
+
+/// How many bytes of the Rust stack calls that recurse on it can use up, counting from where the
+/// outermost of them started. In the Evaluator, only calls made by natives like MAP do that, but
+/// in the TreeWalker every call does. It's well inside of the 2 MiB a new thread gets, and the
+/// 1 MiB a WebAssembly program gets, so programs that go too deep stop with an error instead of
+/// crashing the host, whatever the recursion limit is.
+pub const STACK_LIMIT: usize = 512 << 10;
+
+/// Keeps track of how much of the Rust stack the calls that recurse on it are using.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct StackDepth(Option<usize>);
+impl StackDepth {
+    /// Notes that a call that recurses on the Rust stack is starting, returning whether it's the
+    /// outermost one, which has to `leave` once it's done. If there isn't room for it, the call
+    /// shouldn't be made at all.
+    pub(crate) fn enter(&mut self) -> Result<bool, String> {
+        let marker = 0u8;
+        let here = std::hint::black_box(&marker) as *const u8 as usize;
+        match self.0 {
+            None => {
+                self.0 = Some(here);
+                Ok(true)
+            }
+            Some(base) if base.abs_diff(here) > STACK_LIMIT => Err(stack_error()),
+            Some(_) => Ok(false),
+        }
+    }
+
+    pub(crate) fn leave(&mut self, outermost: bool) {
+        if outermost {
+            self.0 = None;
+        }
+    }
+}
+
+/// How far along a Chunk has gotten in running.
+/// A new Frame is started whenever a procedure or lambda is called.
+#[derive(Clone)]
+struct Frame {
+    chunk: Arc<Chunk>,
+    /// The Chunk's constants, as the NumberMode says they should be. One that doesn't fit only
+    /// stops the program if it's used.
+    literals: Literals,
+    /// The index of the next instruction to run.
+    ip: usize,
+    /// The innermost Scope, if the code isn't running in the Context itself.
+    scope: Option<Arc<Scope>>,
+    /// How tall the stack was when the Frame started, so that a `RETURN` can clean up after it.
+    stack: usize,
+}
+
+/// The locals of one call to a procedure or lambda, kept in slots that were picked out for them
+/// when it was compiled. The lambdas defined during the call hold on to its Scope, so that they can
+/// still get at its variables once it has returned.
+/// A Scope lives until the call that made it is over and nothing holds on to it anymore. A lambda
+/// that's stored in the very Scope it holds on to keeps it around for as long as the Evaluator is.
+pub struct Scope {
+    /// Slots stay empty until something is assigned to them.
+    slots: Mutex<Vec<Option<Var>>>,
+    /// The name of the variable in each slot.
+    names: Arc<[String]>,
+    /// The Scope the procedure or lambda was defined in, or None if it was defined at the top.
+    parent: Option<Arc<Scope>>,
+}
+
+/// Where a variable that's being assigned to is kept.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    Slot(&'a Arc<Scope>, usize),
+    Global(&'a str),
+}
+
+/// A change made to a variable, along with what it was before, which is None if it was new.
+/// Lists can be changed without the variables holding them being changed, so they're kept track
+/// of on their own, along with the items they had.
+enum Write {
+    Slot(Arc<Scope>, usize, Option<Var>),
+    Global(String, Option<Var>),
+    List(List, Saved),
 }
+
 impl Evaluator {
     /// A good context to pass in here is Context::std(), so that the code
     /// that's run using this Evaluator has access to the standard dictionary.
     pub fn new(context: Context) -> Self {
         Self {
-            contexts: vec![context],
+            context,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            recursion_limit: RECURSION_LIMIT,
+            stack_depth: StackDepth::default(),
+            step_limit: None,
+            memory_limits: MemoryLimits::default(),
+            memory_used: Usage::default(),
+            memory_peak: Usage::default(),
+            steps: 0,
+            cancel: CancelHandle::default(),
+            random: Random::unseeded(),
+            number_mode: NumberMode::default(),
+            literals: HashMap::new(),
+            list_mode: ListMode::default(),
+            tape: None,
+            journal: None,
+            displayed: None,
+            observer: None,
+            statements: Statements::default(),
+            resumable: false,
+            waiting: None,
         }
     }
 
-    /// Runs the given AST. May manipulate Contexts stored in the Evaluator.
-    /// Calls itself recursively to evaluate arbitrarily nested blocks.
-    pub fn eval(&mut self, mut ast: Ast, ctx: usize) -> Result<Var, String> {
-        let mut vars = Vec::new();
+    /// Changes how deep procedures can call each other before the program is stopped.
+    /// Calls that natives make stop sooner if they'd use up more than STACK_LIMIT of the Rust stack.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// Limits how many instructions each run can take, so that programs which never end get
+    /// stopped. None lets them run for as long as they like, which is the default.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// Makes RANDOM give the same numbers in the same order as any other Evaluator, or TreeWalker,
+    /// with the same seed. Until this is called, they're different every time.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    /// Changes how arithmetic treats numbers, for every run from now on.
+    pub fn set_number_mode(&mut self, mode: NumberMode) {
+        self.number_mode = mode;
+        self.literals.clear();
+    }
+
+    /// Changes whether giving a variable a list copies it, for every run from now on.
+    pub fn set_list_mode(&mut self, mode: ListMode) {
+        self.list_mode = mode;
+    }
+
+    /// How many instructions the last run took, or the current one has taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Has the Observer watch every program the Evaluator runs from now on.
+    /// Calls that are `RETURN`ed straight away still take over the Frame of the procedure making
+    /// them while it's watching, and it hears about each one through `Observer::tail_call`.
+    pub fn set_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    /// Stops the Observer from watching, giving it back.
+    pub fn take_observer(&mut self) -> Option<Box<dyn Observer>> {
+        self.observer.take()
+    }
+
+    /// A handle that stops the Evaluator when it's cancelled, which can be sent to other threads.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// The Context holding the global variables.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// The Context holding the global variables, for the host to add or change them between runs.
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Compiles and runs the given AST. May manipulate the Context stored in the Evaluator.
+    pub fn eval(&mut self, ast: Ast) -> Result<Var, RuntimeError> {
+        self.run(Arc::new(compile(&ast)?))
+    }
+
+    /// Runs a Chunk of bytecode until it's done, returning the value it leaves behind.
+    pub fn run(&mut self, chunk: Arc<Chunk>) -> Result<Var, RuntimeError> {
+        let depth = self.start(chunk);
+        self.execute(depth)
+    }
+
+    /// Gets a Chunk ready to run without running any of it, returning the depth of its Frame.
+    /// Anything left over from a run that was stopped and never picked back up is thrown away.
+    fn start(&mut self, chunk: Arc<Chunk>) -> usize {
+        self.frames.clear();
+        self.stack.clear();
+        self.waiting = None;
+        self.resumable = false;
+        self.statements = Statements::default();
+        self.steps = 0;
+        self.start_measuring();
+        self.literals.clear();
+        let literals = self.literals(&chunk);
+        self.frames.push(Frame {
+            literals,
+            chunk,
+            ip: 0,
+            scope: None,
+            stack: self.stack.len(),
+        });
+        self.frames.len() - 1
+    }
 
-        while let Some(node) = ast.pop() {
-            eprintln!("evaluating {:?}", node);
-            match node {
-                // Block is like a list, except the commands inside get their own scope
-                // and only the last element is returned.
-                Node::Block(children) => {
-                    eprintln!("evaluating block, depth: {}", ctx);
+    /// Runs instructions until the Frame at the given depth returns.
+    fn execute(&mut self, depth: usize) -> Result<Var, RuntimeError> {
+        self.proceed(depth, None)
+            .map(|var| var.expect("paused without being asked to"))
+    }
 
-                    // run the block in a new scope, reverse the nodes because they're popped in.
-                    let new = self.new_ctx(ctx);
-                    let result = self.eval(children.into_iter().rev().collect(), new)?;
+    /// Runs instructions until the Frame at the given depth returns, or until `pause` says to stop
+    /// before one of them, in which case None is returned and it can be picked up again later.
+    /// If something goes wrong, nothing from the failed run is left lying around.
+    fn proceed(&mut self, depth: usize, pause: Option<Pause>) -> Result<Option<Var>, RuntimeError> {
+        let stack = self.frames[depth].stack;
+        let result = self.instructions(depth, pause);
+        self.settle(depth, stack, result)
+    }
 
-                    // later we'll probably need to figure out return statements.
+    /// Tidies up after the Frame at the given depth stopped running, whose values started at
+    /// that height of the stack, and tells the Observer if the program's over.
+    fn settle(
+        &mut self,
+        depth: usize,
+        stack: usize,
+        result: Result<Option<Var>, RuntimeError>,
+    ) -> Result<Option<Var>, RuntimeError> {
+        let result = result.map_err(|e| e.at(self.line()).traced(|| self.trace()));
+        if result.is_err() {
+            self.frames.truncate(depth);
+            self.stack.truncate(stack);
+        }
 
-                    vars.push(result)
+        // the Observer hears about the end of the program, but not the ends of calls natives make.
+        if let (Some(observer), 0) = (&mut self.observer, depth) {
+            match &result {
+                Ok(Some(_)) => self.statements.finish(|line| observer.statement_end(line)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.statements = Statements::default();
+                    observer.error(e);
                 }
-                Node::List(children) => {
-                    vars.push(self.eval(children.into_iter().rev().collect(), ctx)?);
+            }
+        }
+        result
+    }
+
+    /// Does the actual work of `proceed`, leaving the Frames where something went wrong in place
+    /// so that the error can be traced back to the lines it came from.
+    fn instructions(
+        &mut self,
+        depth: usize,
+        mut pause: Option<Pause>,
+    ) -> Result<Option<Var>, RuntimeError> {
+        loop {
+            let frame = self.frames.last().unwrap();
+            let op = match frame.chunk.code.get(frame.ip) {
+                Some(op) => *op,
+                // the end of a Chunk is reached once the value it produces is on top of the stack.
+                None => {
+                    let frame = self.frames.pop().unwrap();
+                    returned(&mut self.observer, &frame, self.stack.last().unwrap());
+                    if self.frames.len() == depth {
+                        return Ok(self.stack.pop());
+                    }
+                    continue;
                 }
-                Node::Assign(id, val_node) => {
-                    let to = self.eval(vec![*val_node], ctx)?;
-                    eprintln!("assigning {}", id);
-                    self.assign(ctx, id, to);
+            };
+            if pause.as_mut().is_some_and(|pause| pause(self)) {
+                return Ok(None);
+            }
+            if let Some(observer) = &mut self.observer {
+                let started =
+                    (self.statements).advance(&self.frames, |line| observer.statement_end(line));
+                if let Some(line) = started {
+                    observer.statement_start(line);
                 }
-                Node::Call(id, args) => {
-                    //eprintln!("call arg_node: {:?}", arg_node);
-                    let arg = self.eval(args, ctx)?;
+            }
+            self.frame().ip += 1;
+
+            self.steps += 1;
+            if self.step_limit.is_some_and(|limit| self.steps > limit) {
+                return Err(RuntimeError::new("step limit exceeded"));
+            }
+            if self.cancel.is_cancelled() {
+                return Err(RuntimeError::new("cancelled"));
+            }
 
-                    match self.fetch(ctx, id)? {
-                        Var::Function(f) => vars.push(f(Parameters(match arg {
-                            Var::List(args) => args.into_iter().rev().collect(),
-                            _ => vec![arg],
-                        }))),
-                        Var::Lambda(ast) => {
-                            let ast = ast.clone();
-                            vars.push(self.eval(vec![ast], ctx)?);
+            match op {
+                Op::Constant(i) => {
+                    let raw = self.frames.last().unwrap().literals[i as usize].clone();
+                    self.stack.push(Var::Raw(raw.map_err(RuntimeError::new)?));
+                }
+                Op::Load(slot) => {
+                    let var = self.fetch(self.scope(), slot)?;
+                    self.stack.push(var);
+                }
+                Op::Store(slot) => {
+                    let mut to = self.stack.pop().unwrap();
+                    if self.list_mode == ListMode::Copy {
+                        to = to.copied();
+                    }
+                    self.assign(self.scope().cloned(), slot, to);
+                }
+                Op::Call(slot, argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc as usize);
+                    let callee = self.fetch(self.scope(), slot)?;
+                    // the natives calling this run are on the Rust stack, so it can only stop
+                    // for INPUT if there aren't any.
+                    let input = matches!(&callee, Var::Function(name, _) if &**name == "INPUT");
+                    if input && self.resumable && depth == 0 {
+                        if let Some(observer) = &mut self.observer {
+                            observer.call("INPUT", &args);
                         }
-                        _ => panic!("can't call that"),
+                        let prompt = context::input_prompt(&args)?;
+                        match self.replay_input(&prompt)? {
+                            Some(answer) => {
+                                let answer = Var::Raw(Raw::Text(answer));
+                                if let Some(observer) = &mut self.observer {
+                                    observer.returned("INPUT", &answer);
+                                }
+                                self.stack.push(answer);
+                                continue;
+                            }
+                            None => {
+                                self.waiting = Some(prompt);
+                                return Ok(None);
+                            }
+                        }
+                    }
+                    // a call that's RETURNed straight away takes the place of the call it's in,
+                    // so that recursion like this runs without piling up Frames. The program
+                    // itself has nothing to take the place of, and a call with the wrong number
+                    // of arguments is left to fail from where it was made.
+                    let frame = self.frames.last().unwrap();
+                    let tail = frame.chunk.code.get(frame.ip) == Some(&Op::Return)
+                        && frame.scope.is_some()
+                        && matches!(&callee, Var::Compiled(lambda, _)
+                            if lambda.arity as usize == args.len());
+                    if tail {
+                        let frame = self.frames.pop().unwrap();
+                        self.stack.truncate(frame.stack);
+                        if let Some(observer) = &mut self.observer {
+                            observer.tail_call(procedure_name(&frame.chunk));
+                        }
+                    }
+                    if let Some(result) = self.enter(&callee, args)? {
+                        self.stack.push(result);
+                    }
+                }
+                Op::Lambda(i) => {
+                    let lambda = self.chunk().lambdas[i as usize].clone();
+                    let captured = self.scope().cloned();
+                    self.stack.push(Var::Compiled(lambda, captured));
+                }
+                Op::List(n) => {
+                    let mut items = self.stack.split_off(self.stack.len() - n as usize);
+                    if self.list_mode == ListMode::Copy {
+                        items.iter_mut().for_each(|item| *item = item.copied());
+                    }
+                    let list = Var::list(items);
+                    self.measure(&list)?;
+                    self.stack.push(list);
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Return => {
+                    let value = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    returned(&mut self.observer, &frame, &value);
+                    self.stack.truncate(frame.stack);
+                    if self.frames.len() == depth {
+                        return Ok(Some(value));
+                    }
+                    self.stack.push(value);
+                }
+                Op::Jump(to) => self.frame().ip = to as usize,
+                Op::JumpIf(to) => {
+                    if self.stack.pop().unwrap().boolean()? {
+                        self.frame().ip = to as usize;
+                    }
+                }
+                Op::JumpUnless(to) => {
+                    if !self.stack.pop().unwrap().boolean()? {
+                        self.frame().ip = to as usize;
+                    }
+                }
+                Op::Countdown(to) => {
+                    let count = self.stack.last_mut().unwrap();
+                    let left = count.number()?;
+                    if left >= 1.0 {
+                        *count = Var::Raw(Raw::Real(left - 1.0));
+                    } else {
+                        self.stack.pop();
+                        self.frame().ip = to as usize;
                     }
                 }
-                Node::Lambda(ast) => vars.push(self.eval(vec![*ast], ctx)?),
-                Node::Value(raw) => vars.push(Var::Raw(raw)),
-                Node::Var(id) => match id.as_ref() {
-                    "IF" => {}
-                    id => vars.push(match self.fetch(ctx, id)? {
-                        Var::Raw(r) => Var::Raw(r.clone()),
-                        Var::Function(_) => {
-                            return Err("no using functions as variables yet".to_string())
+            }
+        }
+    }
+
+    /// Starts calling a procedure. Natives are run right away and their result is returned, but
+    /// for lambdas, a Frame is pushed for `execute` to run.
+    fn enter(&mut self, callee: &Var, args: Vec<Var>) -> Result<Option<Var>, RuntimeError> {
+        let (lambda, captured) = match callee {
+            Var::Function(name, f) => {
+                if let Some(observer) = &mut self.observer {
+                    observer.call(name, &args);
+                }
+                // natives can change the lists they're given, rather than a variable, so those
+                // are written down first, in case they do.
+                let mut given: Vec<(List, u64, *const Vec<Var>, usize)> = Vec::new();
+                for arg in &args {
+                    match arg {
+                        Var::List(list) if !given.iter().any(|(seen, ..)| seen.same(list)) => {
+                            self.record(|| Write::List(list.clone(), list.save()));
+                            given.push((list.clone(), list.changes(), list.items_id(), list.len()));
                         }
-                        Var::List(_) => return Err("no using lists as variables yet".to_string()),
-                        Var::Lambda(ast) => Var::Lambda(ast.clone()),
-                    }),
-                },
+                        _ => {}
+                    }
+                }
+                let value = match &**name {
+                    "INPUT" if self.tape.is_some() => self.input(&**f, args)?,
+                    _ => f(Parameters(args, self))?,
+                };
+                for (list, changes, id, len) in given {
+                    if list.changes() == changes {
+                        continue;
+                    }
+                    // if its items were shared with a copy, the list has a whole new set now.
+                    let made = match list.items_id() == id {
+                        true => list.len().saturating_sub(len),
+                        false => list.len(),
+                    };
+                    self.measure_items(&list, made)?;
+                    self.changed(&list);
+                }
+                self.measure(&value)?;
+                if let Some(observer) = &mut self.observer {
+                    observer.returned(name, &value);
+                }
+                if let ("DISPLAY", Var::Raw(Raw::Text(text))) = (&**name, &value) {
+                    if let Some(observer) = &mut self.observer {
+                        observer.output(text);
+                    }
+                    if let Some(displayed) = &mut self.displayed {
+                        displayed.push(text.clone());
+                    }
+                }
+                return Ok(Some(value));
             }
+            Var::Compiled(chunk, captured) => (chunk.clone(), captured.clone()),
+            _ => return Err(RuntimeError::new("can't call that")),
+        };
+        if args.len() != lambda.arity as usize {
+            return Err(arity_error(lambda.arity as usize, args.len()).into());
+        }
+        // the first Frame is the program itself, so the rest are calls.
+        if self.frames.len() > self.recursion_limit {
+            return Err(recursion_error(self.recursion_limit).into());
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.call(procedure_name(&lambda), &args);
         }
 
-        Ok(match vars.len() {
-            1 => vars.pop().unwrap(),
-            _ => Var::List(vars),
-        })
+        // the parameters fill the first few slots of the new scope. Outside of it, the
+        // call sees the variables around where the lambda was defined.
+        let names = lambda.locals.clone();
+        let mut slots: Vec<Option<Var>> = args.into_iter().map(Some).collect();
+        slots.resize_with(names.len(), || None);
+        let scope = Scope {
+            slots: Mutex::new(slots),
+            names,
+            parent: captured,
+        };
+        let literals = self.literals(&lambda);
+        self.frames.push(Frame {
+            literals,
+            chunk: lambda,
+            ip: 0,
+            scope: Some(Arc::new(scope)),
+            stack: self.stack.len(),
+        });
+        Ok(None)
     }
 
-    /// This allocates a new empty context on the stack of contexts and returns the index of the
-    /// new context that is created. All values in all ancestors of a context are accessible from
-    /// the child context.
-    fn new_ctx(&mut self, parent: usize) -> usize {
-        self.contexts.push(Context::empty_child(parent));
-        self.contexts.len() - 1
+    /// The Chunk's constants, as the NumberMode says they should be. Each Chunk's are only worked
+    /// out once a run, however many times it's called.
+    fn literals(&mut self, chunk: &Arc<Chunk>) -> Literals {
+        let mode = self.number_mode;
+        let (_, literals) = (self.literals)
+            .entry(Arc::as_ptr(chunk) as usize)
+            .or_insert_with(|| {
+                let literals = chunk.constants.iter().map(|raw| mode.literal(raw.clone()));
+                (chunk.clone(), literals.collect())
+            });
+        literals.clone()
     }
 
-    /// Recursively searches through a given context and then all of its ancestors for a certain
-    /// value.
-    fn fetch(&self, ctx: usize, id: String) -> Result<&Var, String> {
-        let Context { map, parent } = &self.contexts[ctx];
-        map.get(&id)
-            .or_else({
-                let id = id.clone();
-                move || parent.and_then(move |parent| self.fetch(parent, id).ok())
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// The Chunk that's currently being run.
+    fn chunk(&self) -> &Chunk {
+        &self.frames.last().unwrap().chunk
+    }
+
+    /// The line of source code the instruction that's currently being run came from.
+    fn line(&self) -> u32 {
+        self.frames.last().unwrap().line()
+    }
+
+    /// Where each of the calls that are going is at, innermost first.
+    fn trace(&self) -> Vec<TraceFrame> {
+        let frames = self.frames.iter().enumerate().rev();
+        frames
+            .map(|(i, frame)| TraceFrame {
+                procedure: (i > 0).then(|| procedure_name(&frame.chunk).into()),
+                line: frame.line(),
             })
-            .ok_or(format!("couldn't find variable with identifier {}", id))
+            .collect()
+    }
+
+    /// The innermost Scope of the Chunk that's currently being run.
+    fn scope(&self) -> Option<&Arc<Scope>> {
+        self.frames.last().unwrap().scope.as_ref()
+    }
+
+    /// Finds the value of a variable, starting from the given scope.
+    fn fetch(&self, scope: Option<&Arc<Scope>>, slot: Slot) -> Result<Var, String> {
+        match slot {
+            Slot::Local(depth, slot) => {
+                let scope = outer(scope, depth).unwrap();
+                let var = scope.slots.lock().unwrap()[slot as usize].clone();
+                match var {
+                    Some(var) => Ok(var),
+                    // it hasn't been assigned to in this scope yet, so maybe an outer one has it.
+                    None => self.lookup(scope.parent.as_ref(), &scope.names[slot as usize]),
+                }
+            }
+            Slot::Free(name, depth) => {
+                self.lookup(outer(scope, depth), &self.chunk().names[name as usize])
+            }
+        }
+    }
+
+    /// Searches through a given scope, all of its ancestors, and then the Context for a variable
+    /// with a certain name. This is only needed when the compiler can't tell where a variable is.
+    fn lookup(&self, scope: Option<&Arc<Scope>>, id: &str) -> Result<Var, String> {
+        match find(&self.context, scope, id) {
+            Some((Some(scope), slot)) => Ok(scope.slots.lock().unwrap()[slot].clone().unwrap()),
+            Some((None, _)) => Ok(self.context.map[id].clone()),
+            None => Err(format!("couldn't find variable with identifier {}", id)),
+        }
+    }
+
+    /// Stores a new value in a variable. Inside of a procedure, the first assignment to a local
+    /// makes a new variable, unless a procedure or lambda around it already has one with that name,
+    /// which is updated instead. Globals are never updated from inside of a procedure, so that
+    /// a local can't change them just by having the same name. Outside of any procedure, variables
+    /// are kept in the Context, as globals.
+    fn assign(&mut self, scope: Option<Arc<Scope>>, slot: Slot, to: Var) {
+        match slot {
+            Slot::Local(depth, slot) => {
+                let scope = outer(scope.as_ref(), depth).unwrap();
+                let slot = slot as usize;
+                let found = match scope.slots.lock().unwrap()[slot] {
+                    Some(_) => None,
+                    None => find(&self.context, scope.parent.as_ref(), &scope.names[slot]),
+                };
+                let target = match found {
+                    Some((Some(outer), slot)) => Target::Slot(outer, slot),
+                    Some((None, _)) | None => Target::Slot(scope, slot),
+                };
+                self.write(target, to);
+            }
+            Slot::Free(name, _) => {
+                let chunk = self.frames.last().unwrap().chunk.clone();
+                self.write(Target::Global(&chunk.names[name as usize]), to);
+            }
+        }
+    }
+
+    /// Puts a value in a variable, letting the Observer know and keeping track of the change if
+    /// anything wants either of those.
+    fn write(&mut self, target: Target, to: Var) {
+        if let Some(observer) = &mut self.observer {
+            match target {
+                Target::Slot(scope, slot) => {
+                    let old = &scope.slots.lock().unwrap()[slot];
+                    observer.assign(&scope.names[slot], old.as_ref(), &to);
+                }
+                Target::Global(name) => observer.assign(name, self.context.map.get(name), &to),
+            }
+        }
+
+        match target {
+            Target::Slot(scope, slot) => {
+                let old = scope.slots.lock().unwrap()[slot].replace(to);
+                self.record(|| Write::Slot(scope.clone(), slot, old));
+            }
+            Target::Global(name) => {
+                let old = match self.context.map.get_mut(name) {
+                    Some(var) => Some(std::mem::replace(var, to)),
+                    None => self.context.map.insert(name.to_string(), to),
+                };
+                self.record(|| Write::Global(name.to_string(), old));
+            }
+        }
+    }
+
+    /// Lets the Observer know about a list that was changed, once for every variable holding it
+    /// that can be seen from where the program is.
+    fn changed(&mut self, list: &List) {
+        if self.observer.is_none() {
+            return;
+        }
+        let holding = self.variables(self.scope());
+        let observer = self.observer.as_mut().unwrap();
+        for (name, var) in holding {
+            if matches!(&var, Var::List(held) if held.same(list)) {
+                observer.list_mutation(&name, &var);
+            }
+        }
+    }
+
+    /// Every variable that can be seen from a Scope: its own, then those of the Scopes around it,
+    /// and then the globals, sorted by name.
+    fn variables(&self, mut scope: Option<&Arc<Scope>>) -> Vec<(String, Var)> {
+        let mut seen = HashSet::new();
+        let mut variables = Vec::new();
+        while let Some(current) = scope {
+            let slots = current.slots.lock().unwrap();
+            for (name, var) in current.names.iter().zip(slots.iter()) {
+                if let Some(var) = var.as_ref().filter(|_| seen.insert(name)) {
+                    variables.push((name.clone(), var.clone()));
+                }
+            }
+            scope = current.parent.as_ref();
+        }
+
+        let mut globals: Vec<_> = (self.context.map.iter())
+            .filter(|(name, _)| !seen.contains(name))
+            .map(|(name, var)| (name.clone(), var.clone()))
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        variables.extend(globals);
+        variables
+    }
+
+    /// Keeps track of a change made to a variable, if changes are being kept track of.
+    fn record(&mut self, write: impl FnOnce() -> Write) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(write());
+        }
+    }
+
+    /// Puts a variable back the way it was before a change was made to it.
+    fn undo(&mut self, write: Write) {
+        match write {
+            Write::Slot(scope, slot, old) => scope.slots.lock().unwrap()[slot] = old,
+            Write::Global(name, Some(old)) => {
+                self.context.map.insert(name, old);
+            }
+            Write::Global(name, None) => {
+                self.context.map.remove(&name);
+            }
+            Write::List(list, items) => list.set(items),
+        }
+    }
+}
+
+/// The line of the statement each Frame is on, innermost last, for telling when statements start
+/// and end.
+#[derive(Clone, Default)]
+struct Statements(Vec<u32>);
+impl Statements {
+    /// Catches up with the instruction about to be run in the innermost of the Frames, calling
+    /// `ended` with the line of each statement that's over, innermost first. If a new statement is
+    /// starting, its line is returned.
+    fn advance(&mut self, frames: &[Frame], mut ended: impl FnMut(u32)) -> Option<u32> {
+        let frame = frames.last().unwrap();
+        let (depth, line) = (frames.len(), frame.chunk.lines[frame.ip]);
+        // the statements that called Frames which have since returned are done.
+        while self.0.len() > depth {
+            ended(self.0.pop().unwrap());
+        }
+        match self.0.get_mut(depth - 1) {
+            Some(last) if *last == line => return None,
+            Some(last) => ended(std::mem::replace(last, line)),
+            None => self.0.push(line),
+        }
+        Some(line)
+    }
+
+    /// Ends every statement, innermost first, once the program is over.
+    fn finish(&mut self, mut ended: impl FnMut(u32)) {
+        while let Some(line) = self.0.pop() {
+            ended(line);
+        }
+    }
+}
+
+impl Frame {
+    /// The line of source code the instruction this Frame last ran came from. For all but the
+    /// innermost Frame, that's where it called the next one.
+    fn line(&self) -> u32 {
+        self.chunk.lines[self.ip.saturating_sub(1)]
+    }
+}
+
+impl Call for Evaluator {
+    /// Calls a procedure, running it to completion before returning. Natives use this to call
+    /// the procedures they're given, in the middle of a run. This recurses on the Rust stack, so
+    /// it stops the program once that's gotten too deep.
+    fn call(&mut self, procedure: &Var, args: Vec<Var>) -> Result<Var, RuntimeError> {
+        let outermost = self.stack_depth.enter()?;
+        let depth = self.frames.len();
+        let result = match self.enter(procedure, args) {
+            Ok(Some(result)) => Ok(result),
+            Ok(None) => self.execute(depth),
+            Err(e) => Err(e),
+        };
+        self.stack_depth.leave(outermost);
+        result
     }
 
-    /// Stores a new value in the most local context.
-    fn assign(&mut self, ctx: usize, id: String, to: Var) -> Option<Var> {
-        self.contexts[ctx].map.insert(id, to)
+    fn random(&mut self, low: i64, high: i64) -> Result<i64, RuntimeError> {
+        self.random_between(low, high)
     }
+
+    fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+
+    fn list_mode(&self) -> ListMode {
+        self.list_mode
+    }
+}
+
+/// Goes that many scopes out from the given one.
+fn outer(mut scope: Option<&Arc<Scope>>, depth: u16) -> Option<&Arc<Scope>> {
+    for _ in 0..depth {
+        scope = scope?.parent.as_ref();
+    }
+    scope
+}
+
+/// Finds the scope and slot holding a variable with a certain name, searching the given scope
+/// and then all of its ancestors. A scope of None means it's in the Context.
+fn find<'a>(
+    context: &Context,
+    mut scope: Option<&'a Arc<Scope>>,
+    id: &str,
+) -> Option<(Option<&'a Arc<Scope>>, usize)> {
+    while let Some(current) = scope {
+        let found = current.names.iter().position(|n| n == id);
+        if let Some(slot) = found.filter(|&slot| current.slots.lock().unwrap()[slot].is_some()) {
+            return Some((Some(current), slot));
+        }
+        scope = current.parent.as_ref();
+    }
+
+    context.map.contains_key(id).then_some((None, 0))
+}
+
+/// Lets the Observer know that a call is over, if the Frame was for one.
+fn returned(observer: &mut Option<Box<dyn Observer>>, frame: &Frame, value: &Var) {
+    // only calls get a Scope; the program itself doesn't.
+    if let (Some(observer), Some(_)) = (observer, &frame.scope) {
+        observer.returned(procedure_name(&frame.chunk), value);
+    }
+}
+
+/// The name of the procedure a Chunk was compiled from, or `<lambda>` if it doesn't have one.
+fn procedure_name(chunk: &Chunk) -> &str {
+    chunk.name.as_deref().unwrap_or("<lambda>")
+}
+
+/// The error for calling a procedure with the wrong number of arguments.
+pub(crate) fn arity_error(expected: usize, got: usize) -> String {
+    format!("expected {} arguments but got {}", expected, got)
+}
+
+/// The error for calling procedures inside of each other too many times over.
+pub(crate) fn recursion_error(limit: usize) -> String {
+    format!("maximum recursion depth {} exceeded", limit)
+}
+
+/// The error for calls that would use up more of the Rust stack than STACK_LIMIT.
+pub(crate) fn stack_error() -> String {
+    "maximum recursion depth exceeded, there's no room left on the stack".to_string()
 }
 
 #[test]
 fn test_eval() {
+    use super::ast::Node;
+
     fn eval<S: Into<String>>(source: S) -> String {
         eval_ast(vec![
             super::parse(source.into()).expect("couldn't parse source in eval test")
         ])
     }
 
+    /// What the program displays, which has to be the same for the TreeWalker and the Evaluator,
+    /// so that these expectations from before there was an Evaluator hold for both of them.
     fn eval_ast(ast: Ast) -> String {
-        use std::sync::{Arc, Mutex};
-        let mut testing_std = Context::std();
-        let stdout = Arc::new(Mutex::new(String::new()));
-
-        testing_std.map.insert(
-            "DISPLAY".to_string(),
-            Var::Function({
-                let stdout = stdout.clone();
-                Box::new(move |Parameters(args): Parameters| {
-                    let output = args.iter().fold(String::new(), |acc, arg| {
-                        format!("{} {}", acc, arg).trim().to_owned()
-                    });
-                    let mut stdout = stdout.lock().unwrap();
-                    stdout.push_str(&output);
-                    stdout.push(' ');
-                    Var::Raw(Raw::Text(output))
-                })
-            }),
-        );
-
-        Evaluator::new(testing_std)
-            .eval(ast, 0)
+        let run = |walk: bool| -> String {
+            let stdout = Capture::default();
+            let testing_std = Context::with_host(stdout.clone());
+            match walk {
+                true => TreeWalker::new(testing_std).eval(ast.clone()),
+                false => Evaluator::new(testing_std).eval(ast.clone()),
+            }
             .expect("error evaluating");
 
-        let output = stdout.lock().unwrap().to_string();
-        output
+            stdout
+                .output()
+                .iter()
+                .map(|shown| shown.clone() + " ")
+                .collect()
+        };
+        let (walked, ran) = (run(true), run(false));
+        assert_eq!(walked, ran, "the TreeWalker and Evaluator disagree");
+        ran
     }
 
     assert_eq!(eval("DISPLAY(3)"), "3 ".to_string());
@@ -181,18 +968,18 @@ fn test_eval() {
     assert_eq!(
         eval_ast(vec![Node::Call(
             "DISPLAY".to_string(),
-            vec![Node::Value(Raw::Number(-3.0))],
+            vec![Node::Value(Raw::Integer(-3))],
         )]),
         "-3 ".to_string(),
     );
 
     assert_eq!(eval("DISPLAY(3+2-7)"), "-2 ".to_string(),);
 
-    assert_eq!(eval("DISPLAY(3/2*4 + 1 MOD 6)"), "1 ".to_string(),);
+    assert_eq!(eval("DISPLAY(3/2*4 + 1 MOD 6)"), "1.0 ".to_string(),);
 
     assert_eq!(eval("DISPLAY(3 = 4)"), "false ".to_string(),);
     assert_eq!(eval("DISPLAY(4 = 4)"), "true ".to_string(),);
-    //assert_eq!(eval("DISPLAY(4=4.0)"), "true ".to_string(),);
+    assert_eq!(eval("DISPLAY(4=4.0)"), "true ".to_string(),);
     assert_eq!(eval("DISPLAY(\"hi\" = \"no\")"), "false ".to_string(),);
     assert_eq!(eval("DISPLAY(\"hi\" = \"hi\")"), "true ".to_string(),);
     assert_eq!(eval("DISPLAY(true = false)"), "false ".to_string(),);
@@ -221,3 +1008,2496 @@ fn test_eval() {
         "14 1 4 ".to_string()
     )
 }
+
+#[test]
+fn test_globals() {
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator
+        .eval(vec![super::parse("x <- 3").unwrap()])
+        .unwrap();
+    assert_eq!(evaluator.context().map["x"].to_string(), "3");
+
+    evaluator
+        .context_mut()
+        .map
+        .insert("y".to_string(), Var::Raw(Raw::Integer(4)));
+    let sum = evaluator.eval(vec![super::parse("{ z <- 2 \n x + y + z }").unwrap()]);
+    assert_eq!(sum.map(|var| var.to_string()), Ok("9".to_string()));
+    assert_eq!(evaluator.context().map["z"].to_string(), "2");
+
+    evaluator
+        .eval(vec![
+            super::parse("PROCEDURE f(a) { b <- a }\nf(1)").unwrap()
+        ])
+        .unwrap();
+    assert!(!evaluator.context().map.contains_key("a"));
+    assert!(!evaluator.context().map.contains_key("b"));
+}
+
+/// Runs the source code with both the TreeWalker and the Evaluator, making sure they agree.
+#[cfg(test)]
+fn eval_both(source: &str) -> Result<String, String> {
+    let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+    let walked = TreeWalker::new(Context::std()).eval(ast.clone());
+    let ran = Evaluator::new(Context::std()).eval(ast);
+    let (walked, ran) = (
+        walked.map(|v| v.to_string()).map_err(|e| e.to_string()),
+        ran.map(|v| v.to_string()).map_err(|e| e.to_string()),
+    );
+    assert_eq!(
+        walked, ran,
+        "the TreeWalker and Evaluator disagree on {:?}",
+        source
+    );
+    ran
+}
+
+#[test]
+fn test_scoping() {
+    let eval = eval_both;
+
+    // assignment updates a variable that's already around, even from inside of a block.
+    assert_eq!(
+        eval("x <- 1\nIF true { x <- x + 1 }\nRETURN(x)"),
+        Ok("2".to_string())
+    );
+    assert_eq!(
+        eval("x <- 0\nREPEAT 3 TIMES { x <- x + 1 }\nRETURN(x)"),
+        Ok("3".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            n <- 0
+            REPEAT UNTIL n = 5
+            {
+                n <- n + 1
+            }
+            RETURN(n)"
+        ),
+        Ok("5".to_string())
+    );
+
+    // a procedure can read the globals, but assigning to one makes a local of the same name.
+    assert_eq!(
+        eval(
+            "\
+            count <- 0
+            PROCEDURE bump()
+            {
+                count <- count + 1
+                RETURN(count)
+            }
+            RETURN((bump() bump() count))"
+        ),
+        Ok("[1, 1, 0]".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            x <- 1
+            PROCEDURE f()
+            {
+                x <- 5
+                DISPLAY <- 2
+                RETURN(x + DISPLAY)
+            }
+            RETURN((f() x DISPLAY))"
+        ),
+        Ok("[7, 1, DISPLAY]".to_string())
+    );
+
+    // parameters are local to the call, even if there's a variable with the same name around.
+    assert_eq!(
+        eval(
+            "\
+            x <- 1
+            PROCEDURE f(x)
+            {
+                x <- x + 10
+                RETURN(x)
+            }
+            RETURN((f(5) x))"
+        ),
+        Ok("[15, 1]".to_string())
+    );
+
+    // as is the first assignment to a variable inside of a procedure.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE f()
+            {
+                y <- 3
+                RETURN(y)
+            }
+            f()
+            y"
+        ),
+        Err("line 7: couldn't find variable with identifier y".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE f(n)
+            {
+                seen <- n
+                IF n > 0 { f(n - 1) }
+                RETURN(seen)
+            }
+            RETURN(f(3))"
+        ),
+        Ok("3".to_string())
+    );
+
+    // top-level variables are global, even the ones assigned in blocks.
+    assert_eq!(
+        eval(
+            "\
+            IF true { g <- 1 }
+            PROCEDURE read() { RETURN(g) }
+            RETURN(read())"
+        ),
+        Ok("1".to_string())
+    );
+
+    assert_eq!(
+        eval("PROCEDURE f(a, b) { RETURN(a) }\nf(1)"),
+        Err("line 2: expected 2 arguments but got 1".to_string())
+    );
+}
+
+#[test]
+fn test_closures() {
+    let eval = eval_both;
+
+    // each lambda keeps the variables of the call it was made in, even after that call is over.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE makeCounter()
+            {
+                count <- 0
+                next <- | {
+                    count <- count + 1
+                    RETURN(count)
+                }
+                RETURN(next)
+            }
+            c <- makeCounter()
+            c()
+            c()
+            d <- makeCounter()
+            d()
+            RETURN((c() d()))"
+        ),
+        Ok("[3, 2]".to_string())
+    );
+
+    // a procedure sees the variables around where it was defined, not where it was called.
+    assert_eq!(
+        eval(
+            "\
+            x <- \"global\"
+            PROCEDURE show() { RETURN(x) }
+            PROCEDURE caller(x) { RETURN(show()) }
+            RETURN(caller(\"parameter\"))"
+        ),
+        Ok("\"global\"".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE outer(n)
+            {
+                PROCEDURE inner() { RETURN(n * 2) }
+                RETURN(inner())
+            }
+            RETURN(outer(4))"
+        ),
+        Ok("8".to_string())
+    );
+
+    // so they can be handed to other procedures as callbacks.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE twice(action)
+            {
+                action()
+                action()
+            }
+            PROCEDURE count(n)
+            {
+                total <- 0
+                twice(| { total <- total + n })
+                RETURN(total)
+            }
+            RETURN(count(5))"
+        ),
+        Ok("10".to_string())
+    );
+
+    // the caller's locals are out of reach.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE peek() { RETURN(secret) }
+            PROCEDURE hide(secret) { RETURN(peek()) }
+            hide(1)"
+        ),
+        Err("line 1: couldn't find variable with identifier secret".to_string())
+    );
+}
+
+#[test]
+fn test_procedures_as_values() {
+    let eval = eval_both;
+
+    // procedures are printed by name, and can be kept in variables and lists.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE double(x) { RETURN(x * 2) }
+            f <- | { 1 }
+            show <- DISPLAY
+            RETURN((double f show))"
+        ),
+        Ok("[double, <lambda>, DISPLAY]".to_string())
+    );
+    assert_eq!(
+        eval("show <- DISPLAY\nRETURN(show(3))"),
+        Ok("\"3\"".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE double(x) { RETURN(x * 2) }
+            PROCEDURE half(x) { RETURN(x / 2) }
+            PROCEDURE apply(f) { RETURN(f(10)) }
+            RETURN(MAP((double half), apply))"
+        ),
+        Ok("[20, 5]".to_string())
+    );
+
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE double(x) { RETURN(x * 2) }
+            nums <- (1 2 3)
+            RETURN(MAP(nums, double))"
+        ),
+        Ok("[2, 4, 6]".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE big(x) { RETURN(x > 1) }
+            RETURN(FILTER((1 2 3), big))"
+        ),
+        Ok("[2, 3]".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE add(total, x) { RETURN(total + x) }
+            RETURN(REDUCE((1 2 3 4), add, 0))"
+        ),
+        Ok("10".to_string())
+    );
+
+    // the procedures they're given can be closures, and can call them in turn.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE scale(list, factor)
+            {
+                PROCEDURE times(x) { RETURN(x * factor) }
+                RETURN(MAP(list, times))
+            }
+            PROCEDURE sum(list)
+            {
+                PROCEDURE add(a, b) { RETURN(a + b) }
+                RETURN(REDUCE(list, add, 0))
+            }
+            RETURN(sum(scale((1 2 3), 10)))"
+        ),
+        Ok("60".to_string())
+    );
+}
+
+#[test]
+fn test_runtime_errors() {
+    let eval = eval_both;
+
+    // operators given the wrong types stop the program, instead of handing back a message.
+    assert_eq!(
+        eval("x <- 1 + (2 3)"),
+        Err("line 1: Can only apply the add operation to numbers or strings!".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            x <- 1
+            y <- x * 2
+            z <- y AND x
+            RETURN(z)"
+        ),
+        Err("line 3: Can only apply the AND operation to booleans!".to_string())
+    );
+
+    // the line is the one that was running, even when that's inside of a procedure.
+    assert_eq!(
+        eval(
+            "\
+            PROCEDURE check(x)
+            {
+                big <- x > 1
+                RETURN(big)
+            }
+            RETURN(FILTER((1 \"two\" 3), check))"
+        ),
+        Err("line 3: Can't compare text with a number!".to_string())
+    );
+    assert_eq!(
+        eval(
+            "\
+            total <- 0
+            REPEAT UNTIL total > 3
+            {
+                total <- total + 1
+            }
+            RETURN(MAP(total, DISPLAY))"
+        ),
+        Err("line 6: MAP takes a list and a procedure!".to_string())
+    );
+}
+
+#[test]
+fn test_call_stack() {
+    let eval = eval_both;
+
+    // runaway recursion is stopped before it can use up the host's memory.
+    let runaway = "PROCEDURE f(n) { RETURN(1 + f(n + 1)) }\nf(0)";
+    let ast = vec![super::parse(runaway).expect("couldn't parse source in test")];
+    assert_eq!(
+        Evaluator::new(Context::std())
+            .eval(ast.clone())
+            .err()
+            .map(|e| e.to_string()),
+        Some("line 1: maximum recursion depth 1000 exceeded".to_string())
+    );
+    // the TreeWalker, and calls made by natives, recurse on the Rust stack, so they're stopped
+    // once they've used up too much of it, which is well before the limit on a test thread.
+    let out_of_stack = Err(format!("line 1: {}", stack_error()));
+    assert_eq!(
+        TreeWalker::new(Context::std())
+            .eval(ast)
+            .map(|v| v.to_string())
+            .map_err(|e| e.to_string()),
+        out_of_stack
+    );
+    assert_eq!(
+        eval("PROCEDURE f(x) { RETURN(MAP((1 2), f)) }\nf(1)"),
+        out_of_stack
+    );
+
+    // the limit can be changed by the host.
+    fn depth(limit: usize, n: usize) -> (Result<String, String>, Result<String, String>) {
+        let source = format!(
+            "\
+            PROCEDURE count(n)
+            {{
+                IF n = 0 {{ RETURN(0) }}
+                RETURN(1 + count(n - 1))
+            }}
+            RETURN(count({}))",
+            n
+        );
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        let mut walker = TreeWalker::new(Context::std());
+        walker.set_recursion_limit(limit);
+        let mut evaluator = Evaluator::new(Context::std());
+        evaluator.set_recursion_limit(limit);
+        (
+            walker
+                .eval(ast.clone())
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string()),
+            evaluator
+                .eval(ast)
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string()),
+        )
+    }
+    let ok = Ok("4".to_string());
+    assert_eq!(depth(5, 4), (ok.clone(), ok));
+    let err = Err("line 4: maximum recursion depth 5 exceeded".to_string());
+    assert_eq!(depth(5, 5), (err.clone(), err));
+
+    // errors say which calls they happened in, and where each of them was.
+    let source = "\
+        PROCEDURE check(x)
+        {
+            big <- x > 1
+            RETURN(big)
+        }
+        PROCEDURE keep(list)
+        {
+            kept <- FILTER(list, check)
+            RETURN(kept)
+        }
+        RETURN(keep((1 \"two\")))";
+    let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+    let walked = TreeWalker::new(Context::std()).eval(ast.clone()).err();
+    let ran = Evaluator::new(Context::std()).eval(ast).err().unwrap();
+    assert_eq!(walked.as_ref(), Some(&ran));
+    assert_eq!(
+        format!("{:#}", ran),
+        "\
+line 3: Can't compare text with a number!
+    at line 3 in check
+    at line 8 in keep
+    at line 11 in the program"
+    );
+
+    // recursion that hits the limit doesn't repeat the same call over and over.
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_recursion_limit(50);
+    let ast = vec![super::parse(runaway).unwrap()];
+    let error = evaluator.eval(ast).err().unwrap();
+    assert_eq!(error.trace.len(), 51);
+    assert_eq!(
+        format!("{:#}", error),
+        "\
+line 1: maximum recursion depth 50 exceeded
+    at line 1 in f ×50
+    at line 2 in the program"
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    // calls that are RETURNed straight away don't pile up, on either engine, so recursion like
+    // this can go as deep as it likes, even on a test thread's small stack.
+    let sum = "\
+        PROCEDURE sum(n, total)
+        {
+            IF n = 0 { RETURN(total) }
+            RETURN(sum(n - 1, total + n))
+        }";
+    let ast = |n: u32| vec![super::parse(format!("{}\nRETURN(sum({}, 0))", sum, n)).unwrap()];
+    let evaluator = Evaluator::new(Context::std()).eval(ast(1000000));
+    assert_eq!(evaluator.unwrap().to_string(), "500000500000");
+    // the TreeWalker is a good deal slower, so it doesn't go quite as deep.
+    let walker = TreeWalker::new(Context::std()).eval(ast(20000));
+    assert_eq!(walker.unwrap().to_string(), "200010000");
+
+    let source = "\
+        PROCEDURE gcd(a, b)
+        {
+            IF b = 0 { RETURN(a) }
+            RETURN(gcd(b, a MOD b))
+        }
+        PROCEDURE even(n)
+        {
+            IF n = 0 { RETURN(true) }
+            RETURN(odd(n - 1))
+        }
+        PROCEDURE odd(n)
+        {
+            IF n = 0 { RETURN(false) }
+            RETURN(even(n - 1))
+        }
+        RETURN((gcd(1071, 462) even(5001) odd(5001)))";
+    let ast = vec![super::parse(source).unwrap()];
+    let mut walker = TreeWalker::new(Context::std());
+    walker.set_recursion_limit(3);
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_recursion_limit(3);
+    let expected = "[21, false, true]";
+    assert_eq!(walker.eval(ast.clone()).unwrap().to_string(), expected);
+    assert_eq!(evaluator.eval(ast).unwrap().to_string(), expected);
+
+    // calls that still have something left to do after they return do pile up.
+    let ast = vec![super::parse(
+        "PROCEDURE count(n) { IF n = 0 { RETURN(0) }\nRETURN(1 + count(n - 1)) }\ncount(5)",
+    )
+    .unwrap()];
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_recursion_limit(3);
+    assert_eq!(
+        evaluator.eval(ast).err().map(|e| e.to_string()),
+        Some("line 2: maximum recursion depth 3 exceeded".to_string())
+    );
+
+    // a tail call is traced from wherever the call it took the place of was made.
+    let source = "\
+        PROCEDURE outer(x) { RETURN(inner(x)) }
+        PROCEDURE inner(x) { RETURN(x > 1) }
+        outer(\"two\")";
+    let ast = vec![super::parse(source).unwrap()];
+    let walked = TreeWalker::new(Context::std()).eval(ast.clone()).err();
+    let ran = Evaluator::new(Context::std()).eval(ast).err().unwrap();
+    assert_eq!(walked.as_ref(), Some(&ran));
+    assert_eq!(
+        format!("{:#}", ran),
+        "\
+line 2: Can't compare text with a number!
+    at line 2 in inner
+    at line 3 in the program"
+    );
+
+    // but one with the wrong number of arguments fails from where it was made.
+    let source = "\
+        PROCEDURE outer(x) { RETURN(inner(x, x)) }
+        PROCEDURE inner(x) { RETURN(x) }
+        outer(1)";
+    let ast = vec![super::parse(source).unwrap()];
+    let walked = TreeWalker::new(Context::std()).eval(ast.clone()).err();
+    let ran = Evaluator::new(Context::std()).eval(ast).err().unwrap();
+    assert_eq!(walked.as_ref(), Some(&ran));
+    assert_eq!(
+        format!("{:#}", ran),
+        "\
+line 1: expected 1 arguments but got 2
+    at line 1 in outer
+    at line 3 in the program"
+    );
+}
+
+#[test]
+fn test_stopping() {
+    fn eval(evaluator: &mut Evaluator, source: &str) -> Result<String, String> {
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        evaluator
+            .eval(ast)
+            .map(|v| v.to_string())
+            .map_err(|e| e.to_string())
+    }
+    let forever = "\
+        x <- 0
+        REPEAT UNTIL false
+        {
+            x <- x + 1
+            y <- x
+        }";
+
+    // programs that never end run out of steps.
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_step_limit(Some(1000));
+    assert_eq!(
+        eval(&mut evaluator, forever),
+        Err("line 5: step limit exceeded".to_string())
+    );
+    assert_eq!(evaluator.steps(), 1001);
+
+    // the limit is for each run, and ones that stay under it aren't bothered.
+    assert_eq!(eval(&mut evaluator, "RETURN(x)"), Ok("91".to_string()));
+    assert!(evaluator.steps() < 10);
+
+    // they can also be stopped from another thread.
+    let mut evaluator = Evaluator::new(Context::std());
+    let cancel = evaluator.cancel_handle();
+    let timer = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        cancel.cancel();
+    });
+    let result = eval(&mut evaluator, forever);
+    timer.join().unwrap();
+    assert!(
+        matches!(&result, Err(e) if e.ends_with(": cancelled")),
+        "{:?}",
+        result
+    );
+
+    // and stay stopped, until the handle is reset.
+    assert!(evaluator.cancel_handle().is_cancelled());
+    assert_eq!(
+        eval(&mut evaluator, "RETURN(1)"),
+        Err("line 1: cancelled".to_string())
+    );
+    evaluator.cancel_handle().reset();
+    assert_eq!(eval(&mut evaluator, "RETURN(1)"), Ok("1".to_string()));
+
+    // the TreeWalker can be stopped both ways too.
+    let walk = |walker: &mut TreeWalker, source: &str| {
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        walker
+            .eval(ast)
+            .map(|v| v.to_string())
+            .map_err(|e| e.to_string())
+    };
+    let mut walker = TreeWalker::new(Context::std());
+    walker.set_step_limit(Some(1000));
+    assert_eq!(
+        walk(&mut walker, "REPEAT UNTIL false {}"),
+        Err("line 1: step limit exceeded".to_string())
+    );
+    assert_eq!(walker.steps(), 1001);
+    assert_eq!(walk(&mut walker, "RETURN(1)"), Ok("1".to_string()));
+    assert!(walker.steps() < 10);
+
+    let mut walker = TreeWalker::new(Context::std());
+    let cancel = walker.cancel_handle();
+    let timer = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        cancel.cancel();
+    });
+    let result = walk(&mut walker, forever);
+    timer.join().unwrap();
+    assert!(
+        matches!(&result, Err(e) if e.ends_with(": cancelled")),
+        "{:?}",
+        result
+    );
+    walker.cancel_handle().reset();
+    assert_eq!(walk(&mut walker, "RETURN(1)"), Ok("1".to_string()));
+}
+
+#[test]
+fn test_debugger() {
+    let source = "\
+        PROCEDURE double(x)
+        {
+            y <- x * 2
+            RETURN(y)
+        }
+        a <- 1
+        b <- double(a)
+        c <- double(b)
+        RETURN(c)";
+    fn start(source: &str) -> Debugger {
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        Debugger::new(Evaluator::new(Context::std()), ast).unwrap()
+    }
+    fn show(status: Result<Status, RuntimeError>) -> String {
+        match status {
+            Ok(Status::Paused(line)) => format!("paused on {}", line),
+            Ok(Status::Finished(var)) => format!("finished with {}", var),
+            Err(e) => e.to_string(),
+        }
+    }
+    fn variable(frame: &StackFrame, name: &str) -> Option<String> {
+        let mut variables = frame.variables.iter();
+        variables
+            .find(|(n, _)| n == name)
+            .map(|(_, var)| var.to_string())
+    }
+
+    // stepping into goes everywhere, over skips calls, and out finishes them.
+    let mut debugger = start(source);
+    assert!(debugger.call_stack().is_empty());
+    assert_eq!(show(debugger.step_into()), "paused on 1");
+    assert_eq!(show(debugger.step_into()), "paused on 6");
+    assert_eq!(show(debugger.step_into()), "paused on 7");
+    assert_eq!(show(debugger.step_into()), "paused on 3");
+    let stack = debugger.call_stack();
+    assert_eq!(stack.len(), 2);
+    assert_eq!(
+        (stack[0].procedure.as_deref(), stack[0].line),
+        (Some("double"), 3)
+    );
+    assert_eq!((stack[1].procedure.as_deref(), stack[1].line), (None, 7));
+    assert_eq!(stack[0].variables[0].0, "x");
+    assert_eq!(variable(&stack[0], "x"), Some("1".to_string()));
+    assert_eq!(variable(&stack[0], "a"), Some("1".to_string()));
+    assert_eq!(variable(&stack[1], "x"), None);
+    assert_eq!(show(debugger.step_over()), "paused on 4");
+    assert_eq!(
+        variable(&debugger.call_stack()[0], "y"),
+        Some("2".to_string())
+    );
+    assert_eq!(show(debugger.step_out()), "paused on 8");
+    assert_eq!(
+        variable(&debugger.call_stack()[0], "b"),
+        Some("2".to_string())
+    );
+    assert_eq!(show(debugger.step_over()), "paused on 9");
+    assert_eq!(show(debugger.step_over()), "finished with 4");
+    assert_eq!(show(debugger.step_into()), "finished with 4");
+    assert!(debugger.call_stack().is_empty());
+
+    // breakpoints stop the program each time it gets to them.
+    let mut debugger = start(source);
+    debugger.set_breakpoint(4);
+    debugger.set_breakpoint(9);
+    assert_eq!(show(debugger.run_to_breakpoint()), "paused on 4");
+    assert_eq!(
+        variable(&debugger.call_stack()[0], "x"),
+        Some("1".to_string())
+    );
+    assert_eq!(show(debugger.run_to_breakpoint()), "paused on 4");
+    assert_eq!(
+        variable(&debugger.call_stack()[0], "x"),
+        Some("2".to_string())
+    );
+    debugger.clear_breakpoint(9);
+    assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [4]);
+    assert_eq!(show(debugger.run_to_breakpoint()), "finished with 4");
+    let evaluator = debugger.into_evaluator();
+    assert_eq!(evaluator.context().map["c"].to_string(), "4");
+
+    // loops stop on their first line each time around, to check whether they're done.
+    let mut debugger = start(
+        "\
+        n <- 0
+        REPEAT 2 TIMES
+        {
+            n <- n + 1
+            m <- n
+        }
+        RETURN(n)",
+    );
+    let lines: Vec<_> = std::iter::from_fn(|| match debugger.step_over() {
+        Ok(Status::Paused(line)) => Some(line),
+        _ => None,
+    })
+    .collect();
+    assert_eq!(lines, [1, 2, 4, 5, 2, 4, 5, 2, 7]);
+
+    // errors end the program, like they would without the Debugger.
+    let mut debugger = start("x <- 1\ny <- x + (1 2)\nRETURN(y)");
+    assert_eq!(show(debugger.step_over()), "paused on 1");
+    assert_eq!(
+        show(debugger.run_to_breakpoint()),
+        "line 2: Can only apply the add operation to numbers or strings!"
+    );
+    assert!(debugger.call_stack().is_empty());
+
+    // what's DISPLAYed is only shown once, to the Debugger and to an Observer alike.
+    struct Shown(Arc<Mutex<Vec<String>>>);
+    impl Observer for Shown {
+        fn call(&mut self, procedure: &str, _args: &[Var]) {
+            self.0.lock().unwrap().push(format!("call {}", procedure));
+        }
+        fn output(&mut self, text: &str) {
+            self.0.lock().unwrap().push(format!("output {}", text));
+        }
+    }
+    let shown = Arc::new(Mutex::new(Vec::new()));
+    let mut evaluator = Evaluator::new(Context::with_host(Capture::default()));
+    evaluator.set_observer(Box::new(Shown(shown.clone())));
+    let ast = vec![super::parse("DISPLAY(1)\nDISPLAY(2)").unwrap()];
+    let mut debugger = Debugger::new(evaluator, ast).unwrap();
+    assert_eq!(show(debugger.step_over()), "paused on 1");
+    assert_eq!(show(debugger.step_over()), "paused on 2");
+    assert_eq!(debugger.output(), ["1"]);
+    assert_eq!(*shown.lock().unwrap(), ["call DISPLAY", "output 1"]);
+}
+
+#[test]
+fn test_rewinding() {
+    let context = Context::with_host(Capture::default());
+    let ast = vec![super::parse(
+        "\
+        PROCEDURE bump(n)
+        {
+            DISPLAY(total + n)
+            RETURN(total + n)
+        }
+        total <- 0
+        i <- 1
+        REPEAT 3 TIMES
+        {
+            total <- bump(i)
+            i <- i + 1
+        }
+        RETURN(total)",
+    )
+    .expect("couldn't parse source in test")];
+    let mut debugger = Debugger::new(Evaluator::new(context), ast).unwrap();
+    let global = |debugger: &Debugger, name: &str| {
+        let mut variables = debugger.call_stack().pop().unwrap().variables.into_iter();
+        variables
+            .find(|(n, _)| n == name)
+            .map(|(_, var)| var.to_string())
+    };
+
+    debugger.set_breakpoint(11);
+    for _ in 0..3 {
+        assert!(matches!(
+            debugger.run_to_breakpoint(),
+            Ok(Status::Paused(11))
+        ));
+    }
+    assert_eq!(global(&debugger, "total"), Some("6".to_string()));
+    assert_eq!(global(&debugger, "i"), Some("3".to_string()));
+    assert_eq!(debugger.output(), ["1", "3", "6"]);
+    let stopped = debugger.history_len();
+
+    // going back a step puts everything the way it was.
+    assert_eq!(debugger.step_back(), Some(4));
+    assert_eq!(debugger.call_stack()[0].procedure.as_deref(), Some("bump"));
+    assert_eq!(global(&debugger, "total"), Some("3".to_string()));
+    assert_eq!(debugger.output(), ["1", "3", "6"]);
+    assert_eq!(debugger.step_back(), Some(3));
+    assert_eq!(global(&debugger, "total"), Some("3".to_string()));
+    assert_eq!(debugger.output(), ["1", "3"]);
+    assert_eq!(debugger.history_len(), stopped - 2);
+
+    // and running forward from there does everything over again.
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Paused(11))
+    ));
+    assert_eq!(global(&debugger, "total"), Some("6".to_string()));
+    assert_eq!(debugger.output(), ["1", "3", "6"]);
+
+    // it can go all the way back to the start, forgetting variables that weren't around yet.
+    assert_eq!(debugger.rewind(0), Some(1));
+    assert_eq!(global(&debugger, "total"), None);
+    assert!(debugger.output().is_empty());
+    assert_eq!(debugger.step_back(), None);
+
+    // even once the program's over.
+    debugger.clear_breakpoint(11);
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Finished(_))
+    ));
+    assert_eq!(debugger.step_back(), Some(13));
+    assert_eq!(global(&debugger, "total"), Some("6".to_string()));
+    assert_eq!(debugger.rewind(1000), None);
+    assert_eq!(debugger.rewind(1), Some(6));
+    assert!(matches!(debugger.step_over(), Ok(Status::Paused(7))));
+    assert_eq!(global(&debugger, "total"), Some("0".to_string()));
+
+    // a long loop only has its last few statements remembered.
+    let ast = vec![super::parse(
+        "\
+        i <- 0
+        REPEAT 1000 TIMES
+        {
+            i <- i + 1
+        }
+        RETURN(i)",
+    )
+    .expect("couldn't parse source in test")];
+    let mut debugger = Debugger::new(Evaluator::new(Context::std()), ast).unwrap();
+    debugger.set_history_limit(5);
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Finished(_))
+    ));
+    assert_eq!(debugger.history_len(), 5);
+    assert_eq!(debugger.rewind(0), Some(4));
+    assert_eq!(global(&debugger, "i"), Some("998".to_string()));
+    assert!(matches!(debugger.step_over(), Ok(Status::Paused(2))));
+    assert_eq!(global(&debugger, "i"), Some("999".to_string()));
+    assert_eq!(debugger.history_len(), 2);
+
+    // and it's forgotten while the loop is still going, not only once it stops. Each statement
+    // that's remembered holds on to the Frame of the call it's in, so the procedure can tell.
+    let most = Arc::new(Mutex::new(0));
+    let mut context = Context::std();
+    let held = most.clone();
+    let count = move |Parameters(args, _): Parameters| {
+        if let [Var::Compiled(chunk, _)] = args.as_slice() {
+            let mut most = held.lock().unwrap();
+            *most = (*most).max(Arc::strong_count(chunk));
+        }
+        Ok(Var::Raw(Raw::Bool(true)))
+    };
+    (context.map).insert("COUNT".to_string(), Var::native("COUNT", count));
+    let ast = vec![super::parse(
+        "\
+        PROCEDURE spin()
+        {
+            i <- 0
+            REPEAT 10000 TIMES
+            {
+                i <- i + 1
+                COUNT(spin)
+            }
+        }
+        spin()",
+    )
+    .expect("couldn't parse source in test")];
+    let mut debugger = Debugger::new(Evaluator::new(context), ast).unwrap();
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Finished(_))
+    ));
+    let most = *most.lock().unwrap();
+    assert!(
+        most > HISTORY_LIMIT && most < HISTORY_LIMIT + 10,
+        "{}",
+        most
+    );
+}
+
+#[test]
+fn test_trace_table() {
+    fn trace(source: &str) -> (TraceTable, Result<Var, RuntimeError>) {
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        Evaluator::new(Context::std()).trace_table(ast)
+    }
+
+    let (table, result) = trace(
+        "\
+        x <- 1
+        y <- x + 1
+        REPEAT 2 TIMES
+        {
+            x <- x * y
+        }
+        RETURN(x)",
+    );
+    assert_eq!(result.unwrap().to_string(), "4");
+    assert_eq!(table.columns, ["x", "y"]);
+    assert_eq!(
+        table.to_markdown(),
+        "\
+| Line | x | y |
+| --- | --- | --- |
+| 1 | 1 |  |
+| 2 | 1 | 2 |
+| 3 | 1 | 2 |
+| 5 | 2 | 2 |
+| 3 | 2 | 2 |
+| 5 | 4 | 2 |
+| 3 | 4 | 2 |
+| 7 | 4 | 2 |
+"
+    );
+
+    // procedures' variables show up while they're being run, and the statement a procedure
+    // returns from is done once it's back where it was called, before anything's done with what
+    // it returned.
+    let (table, _) = trace(
+        "\
+        PROCEDURE greet(name)
+        {
+            message <- \"hi, \" + name
+            RETURN(message)
+        }
+        said <- greet(\"a|b\")",
+    );
+    assert_eq!(table.columns, ["name", "message", "said"]);
+    assert_eq!(
+        table.rows.iter().map(|row| row.line).collect::<Vec<_>>(),
+        [1, 3, 4, 6]
+    );
+    assert_eq!(
+        table.to_csv(),
+        "\
+Line,name,message,said
+1,,,
+3,\"\"\"a|b\"\"\",\"\"\"hi, a|b\"\"\",
+4,,,
+6,,,\"\"\"hi, a|b\"\"\"
+"
+    );
+    assert_eq!(
+        table.to_html().lines().nth(5),
+        Some("<tr><td>6</td><td></td><td></td><td>&quot;hi, a|b&quot;</td></tr>")
+    );
+    assert!(table.to_markdown().contains("| 6 |  |  | \"hi, a\\|b\" |"));
+
+    // a program that fails still has a table of everything it did.
+    let (table, result) = trace("x <- 1\ny <- x + (1 2)\nz <- 3");
+    assert!(result.is_err());
+    assert_eq!(table.rows.len(), 1);
+}
+
+#[test]
+fn test_host() {
+    let output = Capture::default();
+    let host = Scripted::new(vec!["Ada", "3"], output.clone());
+    let mut evaluator = Evaluator::new(Context::with_host(host));
+    let ast = vec![super::parse(
+        "\
+        name <- INPUT(\"name?\")
+        DISPLAY(\"hi\" name)
+        INPUT()",
+    )
+    .unwrap()];
+    assert_eq!(
+        evaluator.eval(ast).map(|var| var.to_string()),
+        Ok("[\"\"hi\" \"Ada\"\", \"3\"]".to_string())
+    );
+    assert_eq!(output.output(), ["\"hi\" \"Ada\""]);
+
+    let ast = vec![super::parse("DISPLAY(1 2)\nINPUT()").unwrap()];
+    assert_eq!(
+        evaluator.eval(ast).err().map(|e| e.to_string()),
+        Some("line 2: ran out of scripted input".to_string())
+    );
+    assert_eq!(output.output(), ["\"hi\" \"Ada\"", "1 2"]);
+
+    output.clear();
+    let mut evaluator = Evaluator::new(Context::with_host(output.clone()));
+    let ast = vec![super::parse("DISPLAY(4)\nINPUT(1 2)").unwrap()];
+    assert_eq!(
+        evaluator.eval(ast).err().map(|e| e.to_string()),
+        Some("line 2: INPUT takes at most one prompt!".to_string())
+    );
+    let ast = vec![super::parse("INPUT()").unwrap()];
+    assert_eq!(
+        evaluator.eval(ast).err().map(|e| e.to_string()),
+        Some("line 1: there's no input to read".to_string())
+    );
+    assert_eq!(output.output(), ["4"]);
+}
+
+#[test]
+fn test_resuming() {
+    fn show(progress: Result<Progress, RuntimeError>) -> String {
+        match progress {
+            Ok(Progress::Finished(var)) => format!("finished with {}", var),
+            Ok(Progress::NeedsInput(prompt)) => format!("needs input for {:?}", prompt),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    let output = Capture::default();
+    let host = Scripted::new(vec!["from", "the host"], output.clone());
+    let mut evaluator = Evaluator::new(Context::with_host(host));
+    let ast = vec![super::parse(
+        "\
+        PROCEDURE ask(question)
+        {
+            answer <- INPUT(question)
+            RETURN(answer + \"!\")
+        }
+        REPEAT 2 TIMES
+        {
+            DISPLAY(ask(\"name?\"))
+        }
+        INPUT()",
+    )
+    .unwrap()];
+
+    assert_eq!(
+        show(evaluator.resume("early")),
+        "the program isn't waiting for input"
+    );
+    assert_eq!(
+        show(evaluator.run_resumable(ast.clone())),
+        "needs input for \"name?\""
+    );
+    assert_eq!(evaluator.waiting_for_input(), Some("name?"));
+    assert_eq!(show(evaluator.resume("Ada")), "needs input for \"name?\"");
+    assert_eq!(output.output(), ["\"Ada!\""]);
+    assert_eq!(show(evaluator.resume("Grace")), "needs input for \"\"");
+    assert_eq!(show(evaluator.resume("done")), "finished with \"done\"");
+    assert_eq!(output.output(), ["\"Ada!\"", "\"Grace!\""]);
+    assert_eq!(evaluator.waiting_for_input(), None);
+    assert_eq!(
+        show(evaluator.resume("late")),
+        "the program isn't waiting for input"
+    );
+
+    // a run that's stopped at an INPUT and never resumed is thrown away by the next one, inside
+    // of a procedure or not.
+    let asking = vec![super::parse("PROCEDURE ask() { RETURN(INPUT(\"first\")) }\nask()").unwrap()];
+    assert_eq!(
+        show(evaluator.run_resumable(asking)),
+        "needs input for \"first\""
+    );
+    let ast = vec![super::parse("b <- INPUT(\"second\")\nb").unwrap()];
+    assert_eq!(
+        show(evaluator.run_resumable(ast)),
+        "needs input for \"second\""
+    );
+    assert_eq!(show(evaluator.resume("two")), "finished with \"two\"");
+    let asking = vec![super::parse("PROCEDURE ask() { RETURN(INPUT(\"first\")) }\nask()").unwrap()];
+    assert_eq!(
+        show(evaluator.run_resumable(asking)),
+        "needs input for \"first\""
+    );
+    let failed = evaluator.eval(vec![super::parse("x <- 1 + (1 2)").unwrap()]);
+    assert_eq!(
+        format!("{:#}", failed.err().unwrap()),
+        "line 1: Can only apply the add operation to numbers or strings!\n    at line 1 in the program"
+    );
+    assert!(evaluator.frames.is_empty() && evaluator.stack.is_empty());
+    assert_eq!(evaluator.waiting_for_input(), None);
+
+    // a native is in the middle of calling the procedure, so the Host is asked instead.
+    let ast = vec![super::parse("PROCEDURE ask(x) { RETURN(INPUT(x)) }\nMAP((1 2), ask)").unwrap()];
+    assert_eq!(
+        show(evaluator.run_resumable(ast.clone())),
+        "finished with [\"from\", \"the host\"]"
+    );
+    assert_eq!(
+        show(evaluator.run_resumable(ast)),
+        "line 1: ran out of scripted input"
+    );
+
+    // running normally asks the Host, even once a resumable run has stopped partway through,
+    // which is thrown away.
+    let ast = vec![super::parse("INPUT(1 2)").unwrap()];
+    assert_eq!(
+        show(evaluator.run_resumable(ast.clone())),
+        "line 1: INPUT takes at most one prompt!"
+    );
+    let ast = vec![super::parse("x <- INPUT()").unwrap()];
+    assert_eq!(show(evaluator.run_resumable(ast)), "needs input for \"\"");
+    let ast = vec![super::parse("INPUT()").unwrap()];
+    assert_eq!(
+        evaluator.eval(ast).err().map(|e| e.to_string()),
+        Some("line 1: ran out of scripted input".to_string())
+    );
+}
+
+#[test]
+fn test_random() {
+    fn rolls(seed: u64, walk: bool) -> Result<String, String> {
+        let ast = vec![super::parse(
+            "\
+            PROCEDURE roll()
+            {
+                RETURN(RANDOM(1, 6))
+            }
+            (roll() roll() roll() roll() roll() RANDOM(0 - 3, 0 - 1) RANDOM(7, 7))",
+        )
+        .unwrap()];
+        let result = match walk {
+            true => {
+                let mut walker = TreeWalker::new(Context::std());
+                walker.set_seed(seed);
+                walker.eval(ast)
+            }
+            false => {
+                let mut evaluator = Evaluator::new(Context::std());
+                evaluator.set_seed(seed);
+                evaluator.eval(ast)
+            }
+        };
+        result.map(|var| var.to_string()).map_err(|e| e.to_string())
+    }
+
+    // these are the same everywhere, so they're written down to make sure they stay that way.
+    assert_eq!(rolls(42, false), Ok("[5, 1, 2, 3, 1, -1, 7]".to_string()));
+    assert_eq!(rolls(42, true), rolls(42, false));
+    assert_eq!(rolls(7, true), rolls(7, false));
+    assert_ne!(rolls(7, false), rolls(42, false));
+
+    let mut random = Random::new(0);
+    let mut seen = [0; 5];
+    for _ in 0..5000 {
+        seen[(random.between(-2, 2) + 2) as usize] += 1;
+    }
+    assert!(seen.iter().all(|&count| count > 900), "{:?}", seen);
+
+    assert_eq!(
+        eval_both("RANDOM(6, 1)"),
+        Err("line 1: RANDOM's first number can't be bigger than its second!".to_string())
+    );
+    for source in [
+        "RANDOM(1.5, 2)",
+        "RANDOM(1)",
+        "RANDOM(\"1\", 2)",
+        "RANDOM(0, 2.0)",
+    ] {
+        assert_eq!(
+            eval_both(source),
+            Err("line 1: RANDOM takes two integers!".to_string())
+        );
+    }
+}
+
+#[test]
+fn test_replay() {
+    let source = "\
+        name <- INPUT(\"name?\")
+        REPEAT 2 TIMES
+        {
+            DISPLAY(name RANDOM(1, 100))
+        }";
+    let ast = vec![super::parse(source).unwrap()];
+
+    let output = Capture::default();
+    let mut evaluator = Evaluator::new(Context::with_host(Scripted::new(
+        vec!["\"Bobby\"\n\\tables"],
+        output.clone(),
+    )));
+    evaluator.set_seed(1);
+    evaluator.start_recording();
+    evaluator.eval(ast.clone()).unwrap();
+    let recorded = evaluator.stop_tape().unwrap();
+    let text = recorded.to_text();
+    assert_eq!(text.lines().next(), Some("spoodly replay 1"));
+    assert_eq!(
+        text.lines().nth(1),
+        Some(r#"input 1 "name?" "\"Bobby\"\n\\tables""#)
+    );
+    assert_eq!(Replay::from_text(&text), Ok(recorded.clone()));
+
+    // a different seed and no input at all still gets the same run.
+    let replayed = Capture::default();
+    let mut evaluator = Evaluator::new(Context::with_host(replayed.clone()));
+    evaluator.set_seed(2);
+    evaluator.start_replay(Replay::from_text(&text).unwrap());
+    evaluator.eval(ast.clone()).unwrap();
+    assert_eq!(replayed.output(), output.output());
+    assert_eq!(evaluator.stop_tape(), Some(Replay::default()));
+
+    // the answers are replayed rather than asked for when running resumably, too.
+    replayed.clear();
+    evaluator.start_replay(recorded.clone());
+    assert!(matches!(
+        evaluator.run_resumable(ast.clone()),
+        Ok(Progress::Finished(_))
+    ));
+    assert_eq!(replayed.output(), output.output());
+
+    // and they're recorded when they come in through `resume`.
+    evaluator.start_recording();
+    assert!(matches!(
+        evaluator.run_resumable(ast.clone()),
+        Ok(Progress::NeedsInput(_))
+    ));
+    evaluator.resume("Ada").unwrap();
+    let events = evaluator.stop_tape().unwrap().events;
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[0], Event::Input { answer, .. } if answer == "Ada"));
+
+    let diverge = |source: &str| {
+        let mut evaluator = Evaluator::new(Context::with_host(Capture::default()));
+        evaluator.start_replay(recorded.clone());
+        let ast = vec![super::parse(source).unwrap()];
+        evaluator.eval(ast).err().map(|e| e.to_string())
+    };
+    assert_eq!(
+        diverge("DISPLAY(RANDOM(1, 100))"),
+        Some(
+            "line 1: the program called RANDOM(1, 100) on line 1, but when the replay was \
+             recorded it called INPUT(\"name?\") on line 1"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        diverge("x <- 1\nname <- INPUT(\"name?\")"),
+        Some(
+            "line 2: the program called INPUT(\"name?\") on line 2, but when the replay was \
+             recorded it called INPUT(\"name?\") on line 1"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        diverge(&format!("{}\nRANDOM(1, 100)", source)),
+        Some(
+            "line 6: the replay is over, but the program called RANDOM(1, 100) on line 6"
+                .to_string()
+        )
+    );
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.start_replay(Replay::from_text("spoodly replay 1\nrandom 1 1 6 7\n").unwrap());
+    assert_eq!(
+        evaluator
+            .eval(vec![super::parse("RANDOM(1, 6)").unwrap()])
+            .err()
+            .map(|e| e.to_string()),
+        Some(
+            "line 1: the program called RANDOM(1, 6) on line 1, but the replay has it picking 7"
+                .to_string()
+        )
+    );
+
+    assert_eq!(
+        Replay::from_text("input 1 \"a\" \"b\""),
+        Err("a replay has to start with \"spoodly replay 1\"".to_string())
+    );
+    assert_eq!(
+        Replay::from_text("spoodly replay 1\nrandom 1 2 3\n"),
+        Err("line 2 of the replay is broken".to_string())
+    );
+    assert_eq!(
+        Replay::from_text("spoodly replay 1\n\ninput 3 \"a b\"  \"\"\n"),
+        Ok(Replay {
+            events: vec![Event::Input {
+                line: 3,
+                prompt: "a b".to_string(),
+                answer: String::new(),
+            }]
+        })
+    );
+}
+
+#[test]
+fn test_snapshots() {
+    let source = "\
+        PROCEDURE counter()
+        {
+            count <- 0
+            RETURN(| {
+                count <- count + 1
+                RETURN(count)
+            })
+        }
+        PROCEDURE ask(next)
+        {
+            name <- INPUT(\"name?\")
+            DISPLAY(name next() next() RANDOM(1, 1000) 0.1 + 0.2)
+        }
+        tick <- counter()
+        also <- tick
+        REPEAT 2 TIMES
+        {
+            ask(tick)
+        }
+        DISPLAY(also())";
+    let ast = vec![super::parse(source).unwrap()];
+    fn start(output: &Capture) -> Evaluator {
+        let mut evaluator = Evaluator::new(Context::with_host(output.clone()));
+        evaluator.set_seed(3);
+        evaluator
+    }
+
+    let straight = Capture::default();
+    let mut evaluator = start(&straight);
+    evaluator.run_resumable(ast.clone()).unwrap();
+    evaluator.resume("Ada").unwrap();
+    evaluator.resume("Grace \"G\"\n").unwrap();
+
+    // stopped partway through, saved, and picked back up by a brand new Evaluator.
+    let before = Capture::default();
+    let mut evaluator = start(&before);
+    evaluator.run_resumable(ast.clone()).unwrap();
+    evaluator.resume("Ada").unwrap();
+    let snapshot = evaluator.snapshot().unwrap();
+    assert!(snapshot.starts_with("spoodly snapshot 4\n"));
+    drop(evaluator);
+
+    let after = Capture::default();
+    let mut evaluator = Evaluator::restore(&snapshot, Context::with_host(after.clone())).unwrap();
+    assert_eq!(evaluator.snapshot(), Ok(snapshot.clone()));
+    assert_eq!(evaluator.waiting_for_input(), Some("name?"));
+    assert!(matches!(
+        evaluator.resume("Grace \"G\"\n"),
+        Ok(Progress::Finished(_))
+    ));
+    let mut output = before.output();
+    output.extend(after.output());
+    assert_eq!(output, straight.output());
+    assert_eq!(output.len(), 3);
+    assert_eq!(output[2], "5");
+
+    // globals made between runs are kept too, along with the natives stored in them.
+    let mut evaluator = Evaluator::new(Context::with_host(Capture::default()));
+    let ast = vec![super::parse("show <- DISPLAY\nnumbers <- (1 \"two\" (false))").unwrap()];
+    evaluator.eval(ast).unwrap();
+    let snapshot = evaluator.snapshot().unwrap();
+    let restored = Capture::default();
+    let mut evaluator =
+        Evaluator::restore(&snapshot, Context::with_host(restored.clone())).unwrap();
+    let ast = vec![super::parse("show(numbers)").unwrap()];
+    evaluator.eval(ast).unwrap();
+    assert_eq!(restored.output(), ["[1, \"two\", false]"]);
+
+    let mut context = Context::with_host(Capture::default());
+    context.map.remove("DISPLAY");
+    assert_eq!(
+        Evaluator::restore(&snapshot, context).err(),
+        Some("there's no native called DISPLAY to restore".to_string())
+    );
+    assert_eq!(
+        Evaluator::restore("spoodly snapshot 2", Context::std()).err(),
+        Some("a snapshot has to start with \"spoodly snapshot 4\"".to_string())
+    );
+    let cut = &snapshot[..snapshot.len() / 2];
+    assert!(Evaluator::restore(cut, Context::std()).is_err());
+
+    // a Debugger can be snapshotted between any two statements, even inside of a call with
+    // values waiting on the stack, and its NumberMode and ListMode go along with it.
+    let source = "\
+        PROCEDURE add(a, b)
+        {
+            DISPLAY(a)
+            RETURN(a + b)
+        }
+        x <- (1 2)
+        DISPLAY(0.1 + 0.2)
+        y <- 10 + add(1, 2)
+        RETURN(y)";
+    let ast = vec![super::parse(source).unwrap()];
+    let mut evaluator = Evaluator::new(Context::with_host(Capture::default()));
+    evaluator.set_number_mode(NumberMode {
+        exact_decimals: true,
+        ..NumberMode::default()
+    });
+    evaluator.set_list_mode(ListMode::Reference);
+    let mut debugger = Debugger::new(evaluator, ast).unwrap();
+    debugger.set_breakpoint(3);
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Paused(3))
+    ));
+    let snapshot = debugger.snapshot().unwrap();
+
+    let restored = Capture::default();
+    let mut evaluator = Evaluator::restore(
+        &snapshot[..snapshot.find("output").unwrap()],
+        Context::with_host(restored.clone()),
+    )
+    .unwrap();
+    assert!(matches!(
+        evaluator.continue_run(),
+        Ok(Progress::Finished(Var::Raw(Raw::Integer(13))))
+    ));
+    assert_eq!(restored.output(), ["1"]);
+    assert_eq!(
+        evaluator.snapshot().unwrap().lines().last(),
+        Some("lists reference")
+    );
+    assert!(evaluator.continue_run().is_err());
+
+    let mut debugger = Debugger::restore(&snapshot, Context::std()).unwrap();
+    assert_eq!(debugger.output(), ["0.3"]);
+    assert_eq!(debugger.call_stack()[0].procedure.as_deref(), Some("add"));
+    assert_eq!(debugger.call_stack()[0].line, 3);
+    assert!(matches!(debugger.step_over(), Ok(Status::Paused(4))));
+    assert_eq!(debugger.output(), ["0.3", "1"]);
+    assert_eq!(debugger.step_back(), Some(3));
+    assert_eq!(debugger.step_back(), None);
+    assert!(matches!(
+        debugger.run_to_breakpoint(),
+        Ok(Status::Finished(Var::Raw(Raw::Integer(13))))
+    ));
+
+    // snapshots that have been tampered with are turned away, rather than crashing later.
+    let tampered = [
+        ("load local 0 0", "load local 0 7"),
+        ("load local 0 0", "load local 3 0"),
+        ("const 0", "pop"),
+        ("0 0 0 2\n", "0 0 0 1\n"),
+    ];
+    for (from, to) in tampered {
+        assert!(snapshot.contains(from), "{}", from);
+        let snapshot = snapshot.replacen(from, to, 1);
+        assert!(
+            Debugger::restore(&snapshot, Context::std()).is_err(),
+            "{}",
+            to
+        );
+    }
+}
+
+#[test]
+fn test_memory_limits() {
+    fn run(limits: MemoryLimits, source: &str) -> (Result<String, String>, Usage) {
+        let mut evaluator = Evaluator::new(Context::std());
+        evaluator.set_memory_limits(limits);
+        let ast = vec![super::parse(source).unwrap()];
+        let result = evaluator.eval(ast);
+        (
+            result.map(|var| var.to_string()).map_err(|e| e.to_string()),
+            evaluator.memory_peak(),
+        )
+    }
+    let doubling_text = "\
+        s <- \"ab\"
+        REPEAT 10 TIMES
+        {
+            s <- s + s
+        }
+        s";
+    let doubling_list = "\
+        l <- (1 2)
+        REPEAT 10 TIMES
+        {
+            l <- (l l)
+        }
+        0";
+
+    let text = MemoryLimits {
+        text_length: Some(100),
+        ..MemoryLimits::default()
+    };
+    let (result, peak) = run(text, doubling_text);
+    assert_eq!(
+        result,
+        Err(
+            "line 4: memory limit exceeded: text can only be 100 characters long (the peak was \
+             0 list items, 128 characters of text and about "
+                .to_string()
+                + &peak.bytes.to_string()
+                + " bytes)"
+        )
+    );
+    assert_eq!(peak.text_length, 128);
+    assert_eq!(run(text, doubling_list).0, Ok("0".to_string()));
+
+    let items = MemoryLimits {
+        list_items: Some(20),
+        ..MemoryLimits::default()
+    };
+    let (result, peak) = run(items, doubling_list);
+    assert!(result
+        .unwrap_err()
+        .starts_with("line 4: memory limit exceeded: lists can only hold 20 items in all"));
+    assert!(peak.list_items > 20);
+    assert_eq!(run(items, doubling_text).0.map(|s| s.len()), Ok(2050));
+
+    // lists are counted together, however many there are, but not once they're thrown away.
+    let items = MemoryLimits {
+        list_items: Some(30),
+        ..MemoryLimits::default()
+    };
+    let tens = "\
+        a <- (1 2 3 4 5 6 7 8 9 10)
+        b <- (10 9 8 7 6 5 4 3 2 1)";
+    let (result, peak) = run(items, &format!("REPEAT 100 TIMES\n{{\n{}\n}}\n0", tens));
+    assert_eq!(result, Ok("0".to_string()));
+    assert!(peak.list_items > 20 && peak.list_items <= 30);
+    let (result, _) = run(
+        items,
+        &format!("{}\nc <- (a b)\nd <- (1 2 3 4 5 6 7 8 9 10)\n0", tens),
+    );
+    assert!(result
+        .unwrap_err()
+        .starts_with("line 4: memory limit exceeded: lists can only hold 30 items in all"));
+
+    // natives are measured too, like the lists MAP makes.
+    let items = MemoryLimits {
+        list_items: Some(3),
+        ..MemoryLimits::default()
+    };
+    let (result, _) = run(items, "PROCEDURE f(x) { RETURN((x x)) }\nMAP((1 2), f)");
+    assert!(result
+        .unwrap_err()
+        .starts_with("line 2: memory limit exceeded: lists can only hold 3 items in all"));
+
+    let bytes = MemoryLimits {
+        bytes: Some(1000),
+        ..MemoryLimits::default()
+    };
+    let (result, peak) = run(bytes, doubling_text);
+    assert!(result
+        .unwrap_err()
+        .starts_with("line 4: memory limit exceeded: values can only take up about 1000 bytes"));
+    assert!(peak.bytes > 1000 && peak.text_length == 1024);
+
+    // without limits, nothing is measured.
+    let (result, peak) = run(MemoryLimits::default(), doubling_text);
+    assert_eq!(result.map(|s| s.len()), Ok(2050));
+    assert_eq!(peak, Usage::default());
+
+    // and the answers INPUT is given are measured like any other text.
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_memory_limits(text);
+    let ast = vec![super::parse("name <- INPUT(\"name?\")\nname").unwrap()];
+    evaluator.run_resumable(ast).unwrap();
+    let result = evaluator.resume("a".repeat(101));
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .starts_with("line 1: memory limit exceeded: text can only be 100 characters long"));
+    assert_eq!(evaluator.waiting_for_input(), None);
+}
+
+#[test]
+fn test_numbers() {
+    fn exact(source: &str) -> Result<String, String> {
+        let ast = vec![super::parse(source).unwrap()];
+        let mode = NumberMode {
+            exact_decimals: true,
+            ..NumberMode::default()
+        };
+        let mut walker = TreeWalker::new(Context::std());
+        walker.set_number_mode(mode);
+        let mut evaluator = Evaluator::new(Context::std());
+        evaluator.set_number_mode(mode);
+        let (walked, ran) = (
+            walker.eval(ast.clone()).map(|v| v.to_string()),
+            evaluator.eval(ast).map(|v| v.to_string()),
+        );
+        let (walked, ran) = (
+            walked.map_err(|e| e.to_string()),
+            ran.map_err(|e| e.to_string()),
+        );
+        assert_eq!(walked, ran, "the engines disagree on {:?}", source);
+        ran
+    }
+
+    // integers stay integers, unless they're divided unevenly.
+    assert_eq!(
+        eval_both("(1 + 2 7 / 2 6 / 2 7 MOD 3 2.5 * 2 7.5 MOD 2)"),
+        Ok("[3, 3.5, 3, 1, 5.0, 1.5]".to_string())
+    );
+    // big integers don't lose any digits to being reals.
+    assert_eq!(
+        eval_both("(9007199254740993 + 0 9007199254740993 = 9007199254740992)"),
+        Ok("[9007199254740993, false]".to_string())
+    );
+    assert_eq!(
+        eval_both("(4 = 4.0 3 < 3.5 2.0 > 2 0.1 + 0.2 = 0.3)"),
+        Ok("[true, true, false, false]".to_string())
+    );
+    assert_eq!(
+        eval_both("9223372036854775807 + 1"),
+        Err("line 1: The result is too big for an integer!".to_string())
+    );
+    assert_eq!(
+        super::parse("x <- 1\nx <- 9223372036854775808").err(),
+        Some("line 2: 9223372036854775808 is too big for an integer".to_string())
+    );
+    assert_eq!(
+        eval_both("1 / 0"),
+        Err("line 1: Can't divide by zero!".to_string())
+    );
+    assert_eq!(
+        eval_both("5 MOD 0"),
+        Err("line 1: Can't divide by zero!".to_string())
+    );
+
+    // with exact decimals, numbers with points in them add up like money.
+    assert_eq!(
+        exact("(0.1 + 0.2 = 0.3 0.1 + 0.2 1 / 3 2 / 3 7 / 2 1.50 * 2 4 = 4.0)"),
+        Ok(
+            "[true, 0.3, 0.33333333333333333333, 0.66666666666666666667, 3.5, 3.0, true]"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        exact("0.5 / 0"),
+        Err("line 1: Can't divide by zero!".to_string())
+    );
+    assert_eq!(
+        exact("(0 - 0.05 1.25 MOD 0.5 0.1 < 0.25)"),
+        Ok("[-0.05, 0.25, true]".to_string())
+    );
+
+    assert_eq!(
+        "12.50".parse::<Decimal>().map(|d| d.to_string()),
+        Ok("12.5".to_string())
+    );
+    assert_eq!("1e5".parse::<Decimal>(), Err(()));
+    assert_eq!(Decimal::from_real(0.1), Some(Decimal::new(1, 1)));
+    // it comes out the same as reading back the real's shortest digits.
+    let reals = [
+        0.1 + 0.2,
+        1.0 / 3.0,
+        -2.675,
+        1e-10,
+        1e20,
+        2f64.powi(60),
+        123456789.125,
+    ];
+    for real in reals {
+        assert_eq!(
+            Decimal::from_real(real),
+            real.to_string().parse().ok(),
+            "{}",
+            real
+        );
+    }
+    assert_eq!(Decimal::from_real(f64::NAN), None);
+    assert_eq!(Decimal::from_real(1e300), None);
+    assert_eq!(Decimal::new(500, 2).to_string(), "5.0");
+}
+
+#[test]
+fn test_fixed_width() {
+    fn run(mode: NumberMode, source: &str) -> Result<String, String> {
+        let ast = vec![super::parse(source).unwrap()];
+        let mut walker = TreeWalker::new(Context::std());
+        walker.set_number_mode(mode);
+        let mut evaluator = Evaluator::new(Context::std());
+        evaluator.set_number_mode(mode);
+        let walked = walker.eval(ast.clone()).map(|v| v.to_string());
+        let ran = evaluator.eval(ast).map(|v| v.to_string());
+        let (walked, ran) = (
+            walked.map_err(|e| e.to_string()),
+            ran.map_err(|e| e.to_string()),
+        );
+        assert_eq!(walked, ran, "the engines disagree on {:?}", source);
+        ran
+    }
+    let ok = |s: &str| Ok(s.to_string());
+    let wrapping = NumberMode {
+        integer_bits: Some(8),
+        overflow: Overflow::Wrap,
+        ..NumberMode::default()
+    };
+    let checked = NumberMode {
+        integer_bits: Some(8),
+        ..NumberMode::default()
+    };
+    let half = NumberMode {
+        precision: Precision::Half,
+        ..NumberMode::default()
+    };
+    let single = NumberMode {
+        precision: Precision::Single,
+        ..NumberMode::default()
+    };
+
+    // the same program, on different machines.
+    let counting = "x <- 120\nREPEAT 10 TIMES\n{\n    x <- x + 1\n}\nx";
+    assert_eq!(run(NumberMode::default(), counting), ok("130"));
+    assert_eq!(run(wrapping, counting), ok("-126"));
+    assert_eq!(
+        run(checked, counting),
+        Err("line 4: The result is too big for 8 bit integers!".to_string())
+    );
+
+    assert_eq!(
+        run(wrapping, "(127 + 1 0 - 128 - 1 100 * 3 0 - 128 / (0 - 1))"),
+        ok("[-128, 127, 44, -128]")
+    );
+    assert_eq!(
+        run(checked, "200"),
+        Err("line 1: The result is too big for 8 bit integers!".to_string())
+    );
+    assert_eq!(run(checked, "IF false\n{\n    x <- 200\n}\n1"), ok("1"));
+    assert_eq!(
+        run(
+            NumberMode {
+                overflow: Overflow::Wrap,
+                ..NumberMode::default()
+            },
+            "9223372036854775807 + 1"
+        ),
+        ok("-9223372036854775808")
+    );
+
+    // reals lose digits, sooner the fewer bits there are.
+    assert_eq!(
+        run(half, "(2048 + 1.0 0.1 65504.0 65504 * 2.0 0.00000001)"),
+        ok("[2048.0, 0.0999755859375, 65504.0, inf, 0.0]")
+    );
+    assert_eq!(run(single, "0.1 + 0.2"), ok("0.30000001192092896"));
+    assert_eq!(run(single, "16777216 + 1.0"), ok("16777216.0"));
+    assert_eq!(
+        run(NumberMode::default(), "16777216 + 1.0"),
+        ok("16777217.0")
+    );
+
+    assert_eq!(
+        run(wrapping, "(BITS(5) BITS(0 - 1) BITS(127 + 1))"),
+        ok(r#"["00000101", "11111111", "10000000"]"#)
+    );
+    assert_eq!(
+        run(half, "(BITS(0.1) BITS(0 - 2.0) BITS(0.0000000596046))"),
+        ok(r#"["0 01011 1001100110", "1 10000 0000000000", "0 00000 0000000001"]"#)
+    );
+    assert_eq!(
+        run(single, "BITS(1.0)"),
+        ok(r#""0 01111111 00000000000000000000000""#)
+    );
+    assert_eq!(
+        eval_both("(BITS(5) BITS(1.5))"),
+        ok(&format!(
+            r#"["{}101", "0 01111111111 1{}"]"#,
+            "0".repeat(61),
+            "0".repeat(51)
+        ))
+    );
+    assert_eq!(
+        eval_both("BITS(\"five\")"),
+        Err("line 1: BITS takes a number!".to_string())
+    );
+    assert_eq!(
+        run(
+            NumberMode {
+                exact_decimals: true,
+                ..NumberMode::default()
+            },
+            "BITS(0.5)"
+        ),
+        Err(
+            "line 1: BITS can't show an exact decimal, because it isn't stored in binary!"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_lists() {
+    fn run(mode: ListMode, source: &str) -> Result<String, String> {
+        let ast = vec![super::parse(source).unwrap()];
+        let mut walker = TreeWalker::new(Context::std());
+        walker.set_list_mode(mode);
+        let mut evaluator = Evaluator::new(Context::std());
+        evaluator.set_list_mode(mode);
+        let walked = walker.eval(ast.clone()).map(|v| v.to_string());
+        let ran = evaluator.eval(ast).map(|v| v.to_string());
+        let (walked, ran) = (
+            walked.map_err(|e| e.to_string()),
+            ran.map_err(|e| e.to_string()),
+        );
+        assert_eq!(walked, ran, "the engines disagree on {:?}", source);
+        ran
+    }
+    let ok = |s: &str| Ok(s.to_string());
+
+    // procedures change the lists they're given, but only assignment makes another name for one
+    // when lists are shared.
+    let aliasing = "\
+        PROCEDURE grow(list) { APPEND(list, 5) }
+        a <- (1 2)
+        b <- a
+        APPEND(b, 3)
+        grow(a)
+        nested <- (a b)
+        APPEND(a, 6)
+        RETURN((a b nested))";
+    assert_eq!(
+        run(ListMode::Copy, aliasing),
+        ok("[[1, 2, 5, 6], [1, 2, 3], [[1, 2, 5], [1, 2, 3]]]")
+    );
+    assert_eq!(
+        run(ListMode::Reference, aliasing),
+        ok("[[1, 2, 3, 5, 6], [1, 2, 3, 5, 6], [[1, 2, 3, 5, 6], [1, 2, 3, 5, 6]]]")
+    );
+
+    // the lists inside a list that's been copied are still copied when they're changed.
+    let inside = "\
+        PROCEDURE grow(list) { APPEND(list, 0) }
+        outer <- ((1 2) (3 4))
+        copy <- outer
+        MAP(outer, grow)
+        RETURN((outer copy))";
+    assert_eq!(
+        run(ListMode::Copy, inside),
+        ok("[[[1, 2, 0], [3, 4, 0]], [[1, 2], [3, 4]]]")
+    );
+    assert_eq!(
+        run(ListMode::Reference, inside),
+        ok("[[[1, 2, 0], [3, 4, 0]], [[1, 2, 0], [3, 4, 0]]]")
+    );
+
+    // a list can only be put in itself by copying it.
+    let itself = "a <- (1 2)\nAPPEND(a, a)\nRETURN(a)";
+    assert_eq!(run(ListMode::Copy, itself), ok("[1, 2, [1, 2]]"));
+    assert_eq!(
+        run(ListMode::Reference, itself),
+        Err("line 2: Can't put a list inside of itself!".to_string())
+    );
+    let deeper = "a <- (1 2)\nb <- (0 a)\nINSERT(a, 1, b)\nRETURN(a)";
+    assert_eq!(run(ListMode::Copy, deeper), ok("[[0, [1, 2]], 1, 2]"));
+    assert_eq!(
+        run(ListMode::Reference, deeper),
+        Err("line 3: Can't put a list inside of itself!".to_string())
+    );
+
+    assert_eq!(
+        eval_both(
+            "a <- (1 2)\nINSERT(a, 1, 0)\nINSERT(a, 4, 3)\nREMOVE(a, 2)\nRETURN((a LENGTH(a)))"
+        ),
+        ok("[[0, 2, 3], 3]")
+    );
+    assert_eq!(
+        eval_both("a <- (1 2)\nINSERT(a, 4, 3)"),
+        Err("line 2: INSERT's index has to be from 1 to 3!".to_string())
+    );
+    assert_eq!(
+        eval_both("a <- (1 2)\nREMOVE(a, 0)"),
+        Err("line 2: REMOVE's index has to be from 1 to 2!".to_string())
+    );
+    assert_eq!(
+        eval_both("a <- (1 2)\nREMOVE(a, 1)\nREMOVE(a, 1)\nREMOVE(a, 1)"),
+        Err("line 4: Can't REMOVE from an empty list!".to_string())
+    );
+    assert_eq!(
+        eval_both("LENGTH(5)"),
+        Err("line 1: LENGTH takes a list!".to_string())
+    );
+
+    // a host's own procedures could still put a list inside of itself, which is printed and
+    // compared without going round and round.
+    let looped = |n| {
+        let list = List::new(vec![Var::Raw(Raw::Integer(n))]);
+        list.modify(|items| items.push(Var::List(list.clone())));
+        list
+    };
+    let (a, b, c) = (looped(1), looped(1), looped(2));
+    assert_eq!(Var::List(a.clone()).to_string(), "[1, [...]]");
+    assert_eq!(Var::List(a.clone()).equals(&Var::List(b.clone())), Ok(true));
+    assert_eq!(
+        Var::List(a.clone()).equals(&Var::List(c.clone())),
+        Ok(false)
+    );
+    assert!(a.holds(&a) && !a.holds(&b));
+    for list in IntoIterator::into_iter([a, b, c]) {
+        list.modify(|items| items.clear());
+    }
+
+    // copies share their items until one of them is changed.
+    let list = List::new(vec![Var::Raw(Raw::Integer(1))]);
+    let copy = list.copy();
+    assert!(Arc::ptr_eq(&list.items(), &copy.items()) && !list.same(&copy));
+    copy.modify(|items| items.push(Var::Raw(Raw::Integer(2))));
+    assert_eq!((list.len(), copy.len()), (1, 2));
+    // and the lists inside of them aren't copied until they're given out, and then only their
+    // handles are.
+    let outer = List::new(vec![Var::List(list.clone())]);
+    let copy = outer.copy();
+    assert!(Arc::ptr_eq(&outer.peek(), &copy.peek()));
+    match (&outer.items()[0], &copy.items()[0]) {
+        (Var::List(a), Var::List(b)) => {
+            assert!(!a.same(b) && !a.same(&list) && Arc::ptr_eq(&a.peek(), &list.peek()));
+            a.modify(|items| items.clear());
+            assert_eq!((a.len(), b.len(), list.len()), (0, 1, 1));
+        }
+        _ => unreachable!(),
+    }
+
+    // rewinding the Debugger undoes changes made to lists.
+    let ast = vec![super::parse("a <- (1 2)\nAPPEND(a, 3)\nAPPEND(a, 4)").unwrap()];
+    let mut debugger = Debugger::new(Evaluator::new(Context::std()), ast).unwrap();
+    while let Ok(Status::Paused(_)) = debugger.step_over() {}
+    let a = |debugger: &Debugger| debugger.evaluator().context.map["a"].to_string();
+    assert_eq!(a(&debugger), "[1, 2, 3, 4]");
+    debugger.rewind(2);
+    assert_eq!(a(&debugger), "[1, 2, 3]");
+    debugger.rewind(1);
+    assert_eq!(a(&debugger), "[1, 2]");
+
+    // the Observer hears about every variable holding a list that was changed.
+    struct Changes(Arc<Mutex<Vec<String>>>);
+    impl Observer for Changes {
+        fn list_mutation(&mut self, name: &str, list: &Var) {
+            self.0.lock().unwrap().push(format!("{} {}", name, list));
+        }
+    }
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let mut evaluator = Evaluator::new(Context::std());
+    evaluator.set_list_mode(ListMode::Reference);
+    evaluator.set_observer(Box::new(Changes(changes.clone())));
+    let ast = vec![super::parse("a <- (1 2)\nb <- a\nREMOVE(b, 1)").unwrap()];
+    evaluator.eval(ast).unwrap();
+    assert_eq!(*changes.lock().unwrap(), ["a [2]", "b [2]"]);
+
+    // lists that are shared are still shared once they're snapshotted and restored.
+    let snapshot = evaluator.snapshot().unwrap();
+    let mut restored = Evaluator::restore(&snapshot, Context::std()).unwrap();
+    restored.set_list_mode(ListMode::Reference);
+    let ast = vec![super::parse("APPEND(a, 3)\nRETURN(b)").unwrap()];
+    assert_eq!(restored.eval(ast).unwrap().to_string(), "[2, 3]");
+
+    // saving a copy doesn't change it, and the lists inside it are still copied once they're
+    // changed after it's restored.
+    let mut evaluator = Evaluator::new(Context::std());
+    let ast = vec![super::parse("a <- ((1 2) (3 4))\nb <- a").unwrap()];
+    evaluator.eval(ast).unwrap();
+    let shared = |evaluator: &Evaluator| match (
+        &evaluator.context().map["a"],
+        &evaluator.context().map["b"],
+    ) {
+        (Var::List(a), Var::List(b)) => Arc::ptr_eq(&a.peek(), &b.peek()),
+        _ => false,
+    };
+    let snapshot = evaluator.snapshot().unwrap();
+    assert!(shared(&evaluator));
+    let mut restored = Evaluator::restore(&snapshot, Context::std()).unwrap();
+    let ast = "PROCEDURE grow(list) { APPEND(list, 0) }\nMAP(a, grow)\nRETURN((a b))";
+    assert_eq!(
+        restored
+            .eval(vec![super::parse(ast).unwrap()])
+            .unwrap()
+            .to_string(),
+        "[[[1, 2, 0], [3, 4, 0]], [[1, 2], [3, 4]]]"
+    );
+}
+
+#[test]
+fn test_comparisons() {
+    let ok = |s: &str| Ok(s.to_string());
+    let err = |s: &str| Err(format!("line 1: {}", s));
+
+    // lists are equal when their items are.
+    assert_eq!(
+        eval_both(
+            "((1 2 (3 4)) = (1 2 (3 4)) (1 2) = (1 2 3) (1 2) = (1 2.0) ((1 2) 3) = ((1 3) 3))"
+        ),
+        ok("[true, false, true, false]")
+    );
+    assert_eq!(
+        eval_both("(1 2) = (1 \"2\")"),
+        err("Can't compare a number with text!")
+    );
+
+    // text goes in order by its characters, with capitals first.
+    assert_eq!(
+        eval_both("(\"apple\" < \"banana\" \"Zebra\" < \"apple\" \"app\" < \"apple\" \"b\" > \"a\" \"a\" > \"a\")"),
+        ok("[true, true, true, true, false]")
+    );
+
+    // things of different kinds can't be compared at all.
+    assert_eq!(
+        eval_both("\"4\" = 4"),
+        err("Can't compare text with a number!")
+    );
+    assert_eq!(
+        eval_both("true = 1"),
+        err("Can't compare a boolean with a number!")
+    );
+    assert_eq!(
+        eval_both("(1 2) > 1"),
+        err("Can't compare a list with a number!")
+    );
+    assert_eq!(
+        eval_both("true < false"),
+        err("Only numbers and text can be put in order!")
+    );
+    assert_eq!(
+        eval_both("(1 2) < (1 3)"),
+        err("Only numbers and text can be put in order!")
+    );
+
+    // procedures are only equal to themselves.
+    let procedures = "\
+        PROCEDURE f() { RETURN(1) }
+        g <- f
+        a <- | { 1 }
+        b <- | { 1 }
+        RETURN((f = g a = b a = a f = DISPLAY DISPLAY = DISPLAY))";
+    assert_eq!(
+        eval_both(procedures),
+        ok("[true, false, true, false, true]")
+    );
+
+    // NaN isn't equal to, bigger than or smaller than anything, not even itself.
+    assert_eq!(
+        eval_both("n <- 0.0 / 0.0\nRETURN((n = n n < 1 n > 1))"),
+        ok("[false, false, false]")
+    );
+}
+
+#[test]
+fn test_observer() {
+    /// Writes down everything it hears about.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+    impl Observer for Recorder {
+        fn statement_start(&mut self, line: u32) {
+            self.0.lock().unwrap().push(format!("start {}", line));
+        }
+        fn statement_end(&mut self, line: u32) {
+            self.0.lock().unwrap().push(format!("end {}", line));
+        }
+        fn assign(&mut self, name: &str, old: Option<&Var>, new: &Var) {
+            let old = old.map_or("nothing".to_string(), |old| old.to_string());
+            (self.0.lock().unwrap()).push(format!("{}: {} -> {}", name, old, new));
+        }
+        fn call(&mut self, procedure: &str, args: &[Var]) {
+            let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+            (self.0.lock().unwrap()).push(format!("call {}({})", procedure, args.join(", ")));
+        }
+        fn returned(&mut self, procedure: &str, value: &Var) {
+            (self.0.lock().unwrap()).push(format!("{} returned {}", procedure, value));
+        }
+        fn tail_call(&mut self, procedure: &str) {
+            (self.0.lock().unwrap()).push(format!("{} replaced", procedure));
+        }
+        fn output(&mut self, text: &str) {
+            self.0.lock().unwrap().push(format!("output {}", text));
+        }
+        fn error(&mut self, error: &RuntimeError) {
+            self.0.lock().unwrap().push(format!("error {}", error));
+        }
+    }
+
+    fn observe(source: &str) -> Vec<String> {
+        let mut evaluator = Evaluator::new(Context::with_host(Capture::default()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        evaluator.set_observer(Box::new(Recorder(events.clone())));
+        let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+        let _ = evaluator.eval(ast);
+        assert!(evaluator.take_observer().is_some());
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        events
+    }
+
+    assert_eq!(
+        observe(
+            "\
+            PROCEDURE double(x)
+            {
+                RETURN(x * 2)
+            }
+            a <- 1
+            a <- double(a)
+            DISPLAY(a)"
+        ),
+        [
+            "start 1",
+            "double: nothing -> double",
+            "end 1",
+            "start 5",
+            "a: nothing -> 1",
+            "end 5",
+            "start 6",
+            "call double(1)",
+            "start 3",
+            "call *(1, 2)",
+            "* returned 2",
+            "double returned 2",
+            "end 3",
+            "a: 1 -> 2",
+            "end 6",
+            "start 7",
+            "call DISPLAY(2)",
+            "DISPLAY returned \"2\"",
+            "output 2",
+            "end 7",
+        ]
+    );
+
+    // tail calls still take the place of the call they're in, and the Observer is told so.
+    assert_eq!(
+        observe(
+            "\
+            PROCEDURE down(n)
+            {
+                IF n = 0 { RETURN(n) }
+                RETURN(down(n - 1))
+            }
+            down(1)"
+        ),
+        [
+            "start 1",
+            "down: nothing -> down",
+            "end 1",
+            "start 6",
+            "call down(1)",
+            "start 3",
+            "call =(1, 0)",
+            "= returned false",
+            "end 3",
+            "start 4",
+            "call -(1, 1)",
+            "- returned 0",
+            "down replaced",
+            "call down(0)",
+            "end 4",
+            "start 3",
+            "call =(0, 0)",
+            "= returned true",
+            "down returned 0",
+            "end 3",
+            "end 6",
+        ]
+    );
+
+    // errors end the program without ending the statements they happened in.
+    assert_eq!(
+        observe("x <- 1\ny <- x + (1 2)"),
+        [
+            "start 1",
+            "x: nothing -> 1",
+            "end 1",
+            "start 2",
+            "call +(1, [1, 2])",
+            "error line 2: Can only apply the add operation to numbers or strings!",
+        ]
+    );
+}
+
+#[test]
+fn test_memory_stays_flat() {
+    /// Runs a loop that calls a lambda with a block inside of it each time around, and returns
+    /// the most values and frames that the Evaluator ever had to make room for.
+    fn peak(times: usize) -> (usize, usize) {
+        let mut evaluator = Evaluator::new(Context::std());
+        let source = format!(
+            "\
+            step <- | {{
+                next <- total + 1
+                {{
+                    doubled <- next * 2
+                    doubled - next
+                }}
+            }}
+            total <- 0
+            REPEAT {} TIMES {{ total <- step() }}
+            total",
+            times
+        );
+        let total = evaluator.eval(vec![super::parse(source).unwrap()]);
+        assert_eq!(total.map(|var| var.to_string()), Ok(times.to_string()));
+
+        (evaluator.stack.capacity(), evaluator.frames.capacity())
+    }
+
+    assert_eq!(peak(10), peak(100_000));
+
+    // running over and over again in the same Evaluator, like a REPL would, doesn't grow it either.
+    let mut evaluator = Evaluator::new(Context::std());
+    for _ in 0..10_000 {
+        let ast = vec![super::parse("{ x <- 1 \n y <- | { x } \n y() }").unwrap()];
+        evaluator.eval(ast).unwrap();
+        assert!(evaluator.frames.is_empty() && evaluator.stack.is_empty());
+    }
+
+    // once a call is over, the only thing holding on to its scope is the lambda made inside of it.
+    evaluator
+        .eval(vec![super::parse(
+            "\
+            PROCEDURE make()
+            {
+                n <- 0
+                RETURN(| { n })
+            }
+            counter <- make()",
+        )
+        .unwrap()])
+        .unwrap();
+    match &evaluator.context().map["counter"] {
+        Var::Compiled(_, Some(scope)) => assert_eq!(Arc::strong_count(scope), 1),
+        _ => panic!("counter should be a lambda"),
+    }
+}
+
+#[test]
+fn test_differential() {
+    /// Returns what the source code displays and what it returns, run by the TreeWalker if `walk`
+    /// is true and by the Evaluator otherwise.
+    fn run(source: &str, walk: bool) -> (String, Result<String, String>) {
+        let stdout = Capture::default();
+        let testing_std = Context::with_host(stdout.clone());
+
+        let ast = vec![super::parse(source).expect("couldn't parse source in differential test")];
+        let result = match walk {
+            true => TreeWalker::new(testing_std).eval(ast),
+            false => Evaluator::new(testing_std).eval(ast),
+        };
+
+        (
+            stdout
+                .output()
+                .iter()
+                .map(|shown| shown.clone() + " ")
+                .collect(),
+            result.map(|var| var.to_string()).map_err(|e| e.to_string()),
+        )
+    }
+
+    let programs = [
+        "DISPLAY(3/2*4 + 1 MOD 6)",
+        "DISPLAY(1 2 \"three\" true)",
+        "PROCEDURE f(n) { IF n = 0 { RETURN(0) } RETURN(f(n - 1)) }\nDISPLAY(f(3))",
+        "3 + 4",
+        "(1 2 (3 4))",
+        "\
+        s <- 3
+        l <- 4
+        s <- s + l
+        DISPLAY(s l)",
+        "\
+        IF 3 > 4 {
+            DISPLAY(\"yes\")
+        } ELSE {
+            DISPLAY(\"no\")
+        }",
+        "\
+        a <- 2
+        IF (a = 2) { DISPLAY(\"two\") }
+        ELSE { DISPLAY(\"not two\") }",
+        "\
+        total <- 0
+        REPEAT 10 TIMES { total <- total + 2 }
+        DISPLAY(total)",
+        "\
+        n <- 0
+        REPEAT UNTIL n > 4 { n <- n + 1 }
+        DISPLAY(n)",
+        "\
+        n <- 1
+        REPEAT UNTIL n > 100 { n <- n * 2 }
+        DISPLAY(n)",
+        "\
+        n <- 0
+        REPEAT UNTIL n > 3
+        {
+            DISPLAY(n)
+            n <- n + 1
+        }",
+        "\
+        i <- 0
+        REPEAT 3 TIMES {
+            j <- 0
+            REPEAT 2 TIMES { DISPLAY(i j) }
+            i <- i + 1
+        }",
+        "REPEAT 2.5 TIMES { DISPLAY(\"hi\") }",
+        "REPEAT 0 - 1 TIMES { DISPLAY(\"never\") }",
+        "\
+        greet <- | { DISPLAY(\"hi\" name) }
+        name <- \"ada\"
+        greet()
+        name <- \"alan\"
+        greet()",
+        "\
+        f <- | {
+            x <- 1
+            x + 1
+        }
+        DISPLAY(f())",
+        "\
+        x <- 1
+        {
+            x <- 2
+            DISPLAY(x)
+        }
+        DISPLAY(x)",
+        "\
+        f <- | { 3 }
+        f",
+        "\
+        x <- 1
+        {
+            DISPLAY(x)
+            x <- 2
+            {
+                DISPLAY(x)
+                x <- 3
+                DISPLAY(x)
+            }
+            DISPLAY(x)
+        }
+        DISPLAY(x)",
+        "\
+        show <- | { DISPLAY(y) }
+        {
+            y <- 5
+            show()
+        }",
+        "\
+        set <- | { z <- 1 }
+        set()
+        DISPLAY(z)",
+        "\
+        count <- | {
+            n <- 0
+            REPEAT 3 TIMES { n <- n + 1 }
+            n
+        }
+        DISPLAY(count() count())",
+        "x <- (1 2)",
+        "\
+        x <- (1 2)
+        DISPLAY(x)",
+        "\
+        PROCEDURE factorial(n)
+        {
+            IF n < 2
+            {
+                RETURN(1)
+            }
+            RETURN(n * factorial(n - 1))
+        }
+        DISPLAY(factorial(5))",
+        "\
+        PROCEDURE first(limit)
+        {
+            i <- 0
+            REPEAT 10 TIMES
+            {
+                i <- i + 1
+                IF i = limit { RETURN(i) }
+            }
+            RETURN(0 - 1)
+        }
+        DISPLAY(first(4) first(20))",
+        "\
+        PROCEDURE greet(name) { DISPLAY(\"hi\" name) }
+        greet(\"ada\")
+        DISPLAY(name)",
+        "RETURN(4)\nDISPLAY(5)",
+        // errors
+        "DISPLAY(y)",
+        "IF 3 { DISPLAY(1) }",
+        "REPEAT \"a\" TIMES { DISPLAY(1) }",
+        "\
+        x <- 3
+        x()",
+        "\
+        DISPLAY(1)
+        DISPLAY(nope)
+        DISPLAY(2)",
+        "\
+        PROCEDURE f(a) { RETURN(a) }
+        f(1 2)",
+    ];
+
+    for program in programs.iter() {
+        assert_eq!(
+            run(program, true),
+            run(program, false),
+            "the TreeWalker and Evaluator disagree on {:?}",
+            program
+        );
+    }
+}
+
+#[test]
+fn test_threads() {
+    fn send<T: Send>() {}
+    fn sync<T: Send + Sync>() {}
+    send::<Evaluator>();
+    send::<TreeWalker>();
+    send::<Debugger>();
+    sync::<Context>();
+    sync::<Var>();
+
+    // the standard functions are built once, and every submission gets a copy of them.
+    let output = Capture::default();
+    let context = Context::with_host(output.clone());
+    let results: Vec<_> = std::thread::scope(|threads| {
+        let graders: Vec<_> = (1..=200)
+            .map(|n| {
+                let context = &context;
+                threads.spawn(move || {
+                    let source = format!(
+                        "\
+                        PROCEDURE total(n)
+                        {{
+                            sum <- 0
+                            REPEAT n TIMES {{ sum <- sum + n }}
+                            RETURN(sum)
+                        }}
+                        DISPLAY(total({}))
+                        RETURN(total({}))",
+                        n, n
+                    );
+                    let ast = vec![super::parse(source).expect("couldn't parse source in test")];
+                    (Evaluator::new(context.clone()).eval(ast))
+                        .map(|var| var.to_string())
+                        .map_err(|e| e.to_string())
+                })
+            })
+            .collect();
+        graders
+            .into_iter()
+            .map(|grader| grader.join().unwrap())
+            .collect()
+    });
+    let expected: Vec<_> = (1..=200).map(|n| Ok((n * n).to_string())).collect();
+    assert_eq!(results, expected);
+    let mut displayed = output.output();
+    displayed.sort_by_key(|line| line.parse::<i64>().unwrap());
+    let expected: Vec<_> = (1..=200).map(|n| (n * n).to_string()).collect();
+    assert_eq!(displayed, expected);
+
+    // and an Evaluator that's already been used can carry on somewhere else.
+    let mut evaluator = Evaluator::new(Context::std());
+    let ast = vec![super::parse("xs <- [1, 2]").expect("couldn't parse source in test")];
+    evaluator.eval(ast).unwrap();
+    let moved = std::thread::spawn(move || {
+        let ast = vec![super::parse("APPEND(xs, 3)\nRETURN(xs)").unwrap()];
+        evaluator.eval(ast).map(|var| var.to_string()).ok()
+    });
+    assert_eq!(moved.join().unwrap().as_deref(), Some("[1, 2, 3]"));
+
+    // runaway recursion through a native is stopped before it overflows the stack of a thread
+    // that was spawned with the default size, rather than aborting the whole grader.
+    let runaway = std::thread::spawn(|| {
+        let ast = vec![super::parse("PROCEDURE f(x) { RETURN(MAP((1 2), f)) }\nf(1)").unwrap()];
+        let walked = TreeWalker::new(Context::std()).eval(ast.clone());
+        let ran = Evaluator::new(Context::std()).eval(ast);
+        (
+            walked.map_err(|e| e.to_string()),
+            ran.map_err(|e| e.to_string()),
+        )
+    });
+    let out_of_stack = format!("line 1: {}", stack_error());
+    let (walked, ran) = runaway.join().unwrap();
+    assert_eq!(walked.err().as_ref(), Some(&out_of_stack));
+    assert_eq!(ran.err().as_ref(), Some(&out_of_stack));
+}
@@ -0,0 +1,501 @@
+use crate::Raw;
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// How arithmetic is done, which the host picks for the Evaluator or TreeWalker.
+/// The default is 64 bit integers that stop the program when they overflow, and 64 bit reals,
+/// but narrower ones can be picked to show what happens on a smaller machine.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NumberMode {
+    /// Numbers with a decimal point in them are kept as exact Decimals rather than reals, and so
+    /// are integers that are divided unevenly, so that `0.1 + 0.2 = 0.3` like it does with money.
+    pub exact_decimals: bool,
+    /// How many bits integers are stored in, as signed two's complement numbers. None is 64, and
+    /// anything outside of 1 to 64 is treated as the closest of those.
+    pub integer_bits: Option<u32>,
+    /// What happens when an integer doesn't fit in its bits.
+    pub overflow: Overflow,
+    /// How many bits reals are stored in. Every real a program makes is rounded to fit.
+    pub precision: Precision,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    /// The program is stopped with an error.
+    #[default]
+    Error,
+    /// The number wraps around, like it does in most processors, so that one more than the
+    /// biggest integer is the smallest one.
+    Wrap,
+}
+
+/// The sizes of IEEE 754 floating point number there are.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Precision {
+    /// 16 bits, with 10 bits of fraction, which is only about 3 digits.
+    Half,
+    /// 32 bits, with 23 bits of fraction, or about 7 digits.
+    Single,
+    /// 64 bits, with 52 bits of fraction, or about 16 digits.
+    #[default]
+    Double,
+}
+
+impl NumberMode {
+    /// What a number written in the source code should be, with this mode.
+    pub(crate) fn literal(self, raw: Raw) -> Result<Raw, String> {
+        Ok(match raw {
+            Raw::Real(real) if self.exact_decimals => {
+                Decimal::from_real(real).map_or(Raw::Real(self.real(real)), Raw::Decimal)
+            }
+            Raw::Real(real) => Raw::Real(self.real(real)),
+            Raw::Integer(integer) => Raw::Integer(self.integer(integer as i128)?),
+            raw => raw,
+        })
+    }
+
+    fn bits(self) -> u32 {
+        self.integer_bits.unwrap_or(64).clamp(1, 64)
+    }
+
+    /// The integer, made to fit in the integer bits, or an error if it doesn't and it can't wrap.
+    pub(crate) fn integer(self, integer: i128) -> Result<i64, String> {
+        let bits = self.bits();
+        let (smallest, biggest) = (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1);
+        if (smallest..=biggest).contains(&integer) {
+            return Ok(integer as i64);
+        }
+        match (self.overflow, self.integer_bits) {
+            // shifting the low bits to the top and back again copies the sign bit downwards.
+            (Overflow::Wrap, _) => Ok(((integer << (128 - bits)) >> (128 - bits)) as i64),
+            (Overflow::Error, None) => Err("The result is too big for an integer!".to_string()),
+            (Overflow::Error, Some(_)) => {
+                Err(format!("The result is too big for {} bit integers!", bits))
+            }
+        }
+    }
+
+    /// The closest real to this one that fits in the precision.
+    pub(crate) fn real(self, real: f64) -> f64 {
+        match self.precision {
+            Precision::Half => from_half(to_half(real)),
+            Precision::Single => real as f32 as f64,
+            Precision::Double => real,
+        }
+    }
+
+    /// How a number is stored in memory with this mode, as ones and zeros. Integers are written
+    /// as two's complement, and reals as their sign, exponent and fraction, with spaces between.
+    /// None for numbers that aren't stored in binary.
+    pub(crate) fn bit_pattern(self, raw: &Raw) -> Option<String> {
+        match raw {
+            Raw::Integer(integer) => {
+                let bits = self.bits() as usize;
+                let pattern = format!("{:064b}", integer);
+                Some(pattern[64 - bits..].to_string())
+            }
+            Raw::Real(real) => {
+                let (pattern, exponent_bits, width) = match self.precision {
+                    Precision::Half => (to_half(*real) as u64, 5, 16),
+                    Precision::Single => ((*real as f32).to_bits() as u64, 8, 32),
+                    Precision::Double => (real.to_bits(), 11, 64),
+                };
+                let pattern = format!("{:01$b}", pattern, width);
+                Some(format!(
+                    "{} {} {}",
+                    &pattern[..1],
+                    &pattern[1..1 + exponent_bits],
+                    &pattern[1 + exponent_bits..]
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The bits of the closest half precision number to the real, rounding halfway cases to the
+/// one that ends in a zero, like the processor does for the other sizes.
+fn to_half(real: f64) -> u16 {
+    let sign = if real.is_sign_negative() { 0x8000 } else { 0 };
+    if real.is_nan() {
+        return sign | 0x7E00;
+    }
+    let real = real.abs();
+    // everything from here on is a whole number of 2^-24ths, the smallest half there is, so
+    // that the subnormal numbers near zero come out right without being a special case.
+    let exponent = match real {
+        r if r >= 65520.0 => return sign | 0x7C00,
+        r if r < 2f64.powi(-14) => -14,
+        r => r.log2().floor() as i32,
+    };
+    // log2 can be off by one right next to a power of two, so it's checked.
+    let exponent = match real {
+        r if r < 2f64.powi(exponent) && exponent > -14 => exponent - 1,
+        r if r >= 2f64.powi(exponent + 1) => exponent + 1,
+        _ => exponent,
+    };
+    // the number as a whole number of the units the last bit of its fraction stands for.
+    let scaled = real * 2f64.powi(10 - exponent);
+    let mut units = scaled.round_ties_even() as u32;
+    let mut exponent = exponent;
+    if units == 1 << 11 {
+        units >>= 1;
+        exponent += 1;
+    }
+    if exponent > 15 {
+        return sign | 0x7C00;
+    }
+    match units < 1 << 10 {
+        true => sign | units as u16,
+        false => sign | ((exponent + 15) as u16) << 10 | (units as u16 & 0x3FF),
+    }
+}
+
+fn from_half(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let (exponent, fraction) = ((bits >> 10) & 0x1F, (bits & 0x3FF) as f64);
+    sign * match exponent {
+        0 => fraction * 2f64.powi(-24),
+        0x1F if fraction == 0.0 => f64::INFINITY,
+        0x1F => f64::NAN,
+        e => (fraction + 1024.0) * 2f64.powi(e as i32 - 25),
+    }
+}
+
+/// A number with a fixed number of digits after the decimal point, which adds, subtracts and
+/// multiplies without rounding. Dividing rounds to `DIVISION_DIGITS` digits after the point.
+/// It's always kept without any zeros at the end, so that equal Decimals look the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    digits: i128,
+    /// How many of the digits are after the decimal point.
+    scale: u32,
+}
+
+/// How many digits after the decimal point are kept when Decimals are divided.
+pub const DIVISION_DIGITS: u32 = 20;
+
+impl Decimal {
+    /// The number `digits / 10^scale`.
+    pub fn new(mut digits: i128, mut scale: u32) -> Self {
+        while scale > 0 && digits % 10 == 0 {
+            digits /= 10;
+            scale -= 1;
+        }
+        Self { digits, scale }
+    }
+
+    /// The Decimal with the fewest digits that reads back as the real, which for a number that
+    /// was typed in, is the number that was typed. None if it isn't a number, or it's too big or
+    /// too small for the digits to fit.
+    pub fn from_real(real: f64) -> Option<Self> {
+        if !real.is_finite() {
+            return None;
+        }
+        if real == 0.0 {
+            return Some(Self::new(0, 0));
+        }
+        // the real is exactly `mantissa * 2^exponent`.
+        let bits = real.to_bits();
+        let (biased, fraction) = (
+            (bits >> 52 & 0x7FF) as i32,
+            (bits & ((1 << 52) - 1)) as u128,
+        );
+        let (mantissa, exponent) = match biased {
+            0 => (fraction, -1074),
+            _ => (fraction | 1 << 52, biased - 1075),
+        };
+        // anything within half of 2^exponent of the real reads back as it, except that just below
+        // a power of two the reals are twice as close together. Halfway reads back as whichever
+        // has an even mantissa.
+        let below = if mantissa == 1 << 52 && biased > 1 {
+            1
+        } else {
+            2
+        };
+        let (low, high) = (4 * mantissa - below, 4 * mantissa + 2);
+        // whether `top / bottom`, counted in 2^exponents, reads back as the real.
+        let reads_back = |top: Option<u128>, bottom: u128| {
+            let top = top.and_then(|top| top.checked_mul(4));
+            match (top, bottom.checked_mul(low), bottom.checked_mul(high)) {
+                (Some(top), Some(low), Some(high)) => match (top.cmp(&low), top.cmp(&high)) {
+                    (Ordering::Less, _) | (_, Ordering::Greater) => false,
+                    (Ordering::Equal, _) | (_, Ordering::Equal) => mantissa % 2 == 0,
+                    _ => true,
+                },
+                _ => false,
+            }
+        };
+        let signed = |digits: u128| match real < 0.0 {
+            true => -(digits as i128),
+            false => digits as i128,
+        };
+
+        if exponent <= 0 {
+            // a number with a fraction gets as few digits after the point as it can.
+            // 10^scale is 5^scale * 2^scale, and the twos cancel out with the ones the real is
+            // divided by as far as they can, to keep the numbers small enough to fit.
+            let shift = exponent.unsigned_abs();
+            let shl = |n: u128, by: u32| n.checked_shl(by).filter(|&shifted| shifted >> by == n);
+            (0..=38).find_map(|scale| {
+                let (up, down) = (shift.saturating_sub(scale), scale.saturating_sub(shift));
+                let power = shl(5u128.checked_pow(scale)?, down)?;
+                let scaled = mantissa.checked_mul(power)?;
+                let half = if up > 0 { 1 << (up - 1).min(127) } else { 0 };
+                let digits = scaled.checked_add(half)?.checked_shr(up).unwrap_or(0);
+                reads_back(shl(digits, up), power).then(|| Self::new(signed(digits), scale))
+            })
+        } else {
+            // a whole number too big for every one of them to be a real gets as many zeros on
+            // the end as it can.
+            let whole = (exponent < 72).then(|| mantissa << exponent)?;
+            (0..=38).rev().find_map(|zeros| {
+                let power = 10u128.checked_pow(zeros)?;
+                let digits = (whole + power / 2) / power * power;
+                reads_back(Some(digits), 1 << exponent).then(|| Self::new(signed(digits), 0))
+            })
+        }
+    }
+
+    pub fn to_real(self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    /// Both numbers with the same scale, so that their digits can be worked with directly.
+    fn align(self, other: Self) -> Option<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        let widen = |d: Decimal| d.digits.checked_mul(10i128.checked_pow(scale - d.scale)?);
+        Some((widen(self)?, widen(other)?, scale))
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Self::new(a.checked_add(b)?, scale))
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Self::new(a.checked_sub(b)?, scale))
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let digits = self.digits.checked_mul(other.digits)?;
+        Some(Self::new(digits, self.scale + other.scale))
+    }
+
+    /// Rounds halves away from zero. None if `other` is zero, or the answer is too big.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.digits == 0 {
+            return None;
+        }
+        // the answer's scale is the scale of what's divided, less the scale of what it's divided
+        // by, so what's divided is given enough zeros to end up with the digits that are wanted.
+        let zeros = (DIVISION_DIGITS + other.scale).saturating_sub(self.scale);
+        let widened = self.digits.checked_mul(10i128.checked_pow(zeros)?)?;
+        let scale = self.scale + zeros - other.scale;
+        let (quotient, remainder) = (widened / other.digits, widened % other.digits);
+        let round = remainder.unsigned_abs() * 2 >= other.digits.unsigned_abs();
+        let away = if (widened < 0) == (other.digits < 0) {
+            1
+        } else {
+            -1
+        };
+        Some(Self::new(quotient + if round { away } else { 0 }, scale))
+    }
+
+    /// The remainder, which has the same sign as `self`. None if `other` is zero.
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        let (a, b, scale) = self.align(other)?;
+        Some(Self::new(a.checked_rem(b)?, scale))
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(integer: i64) -> Self {
+        Self::new(integer as i128, 0)
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.align(*other) {
+            Some((a, b, _)) => a.partial_cmp(&b),
+            None => self.to_real().partial_cmp(&other.to_real()),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!(
+            "{:0>1$}",
+            self.digits.unsigned_abs(),
+            self.scale as usize + 1
+        );
+        let (whole, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let sign = if self.digits < 0 { "-" } else { "" };
+        match fraction {
+            "" => write!(f, "{}{}.0", sign, whole),
+            fraction => write!(f, "{}{}.{}", sign, whole, fraction),
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ();
+
+    /// Reads a number like `-12.50`, without an exponent.
+    fn from_str(text: &str) -> Result<Self, ()> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+            return Err(());
+        }
+        let digits: i128 = format!("{}{}", whole, fraction).parse().map_err(|_| ())?;
+        let digits = if negative { -digits } else { digits };
+        Ok(Self::new(digits, fraction.len() as u32))
+    }
+}
+
+/// One of the things the arithmetic operators do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+/// A number of any kind, ordered from the most exact to the least.
+#[derive(Clone, Copy)]
+enum Num {
+    Integer(i64),
+    Decimal(Decimal),
+    Real(f64),
+}
+
+impl Num {
+    fn of(raw: &Raw) -> Option<Self> {
+        match raw {
+            Raw::Integer(i) => Some(Num::Integer(*i)),
+            Raw::Decimal(d) => Some(Num::Decimal(*d)),
+            Raw::Real(r) => Some(Num::Real(*r)),
+            _ => None,
+        }
+    }
+
+    fn real(self) -> f64 {
+        match self {
+            Num::Integer(i) => i as f64,
+            Num::Decimal(d) => d.to_real(),
+            Num::Real(r) => r,
+        }
+    }
+
+    /// Only called on Integers and Decimals.
+    fn decimal(self) -> Decimal {
+        match self {
+            Num::Integer(i) => i.into(),
+            Num::Decimal(d) => d,
+            Num::Real(_) => unreachable!("reals aren't turned back into decimals"),
+        }
+    }
+}
+
+const DIVIDE_BY_ZERO: &str = "Can't divide by zero!";
+
+/// Does arithmetic on two numbers, keeping the answer as exact as the numbers it came from.
+/// Integers stay integers unless they're divided unevenly, mixing in a Decimal makes a Decimal,
+/// and mixing in a real makes a real. None if they aren't both numbers.
+pub(crate) fn arithmetic(
+    operation: Operation,
+    a: &Raw,
+    b: &Raw,
+    mode: NumberMode,
+) -> Option<Result<Raw, String>> {
+    use Operation::*;
+
+    Some(match (Num::of(a)?, Num::of(b)?) {
+        (Num::Integer(a), Num::Integer(b)) => {
+            let (a, b) = (a as i128, b as i128);
+            let answer = match operation {
+                Add => a + b,
+                Subtract => a - b,
+                Multiply => a * b,
+                Divide | Modulo if b == 0 => return Some(Err(DIVIDE_BY_ZERO.to_string())),
+                Divide if a % b != 0 => {
+                    return Some(Ok(match mode.exact_decimals {
+                        true => match Decimal::new(a, 0).checked_div(Decimal::new(b, 0)) {
+                            Some(d) => Raw::Decimal(d),
+                            None => Raw::Real(mode.real(a as f64 / b as f64)),
+                        },
+                        false => Raw::Real(mode.real(a as f64 / b as f64)),
+                    }))
+                }
+                Divide => a / b,
+                Modulo => a % b,
+            };
+            mode.integer(answer).map(Raw::Integer)
+        }
+        (a @ Num::Real(_), b) | (a, b @ Num::Real(_)) => {
+            let (a, b) = (a.real(), b.real());
+            Ok(Raw::Real(mode.real(match operation {
+                Add => a + b,
+                Subtract => a - b,
+                Multiply => a * b,
+                Divide => a / b,
+                Modulo => a % b,
+            })))
+        }
+        (a, b) => {
+            let (a, b) = (a.decimal(), b.decimal());
+            if matches!(operation, Divide | Modulo) && b == Decimal::from(0) {
+                return Some(Err(DIVIDE_BY_ZERO.to_string()));
+            }
+            let answer = match operation {
+                Add => a.checked_add(b),
+                Subtract => a.checked_sub(b),
+                Multiply => a.checked_mul(b),
+                Divide => a.checked_div(b),
+                Modulo => a.checked_rem(b),
+            };
+            answer
+                .map(Raw::Decimal)
+                .ok_or_else(|| "The result is too big for a decimal!".to_string())
+        }
+    })
+}
+
+/// Compares two numbers of any kind by their values, so that `4 = 4.0`.
+/// None if they aren't both numbers, or if one of them is NaN.
+pub(crate) fn compare(a: &Raw, b: &Raw) -> Option<Ordering> {
+    match (Num::of(a)?, Num::of(b)?) {
+        (Num::Integer(a), Num::Integer(b)) => Some(a.cmp(&b)),
+        (Num::Integer(i), Num::Real(r)) => compare_exactly(i, r),
+        (Num::Real(r), Num::Integer(i)) => compare_exactly(i, r).map(Ordering::reverse),
+        (a @ Num::Real(_), b) | (a, b @ Num::Real(_)) => a.real().partial_cmp(&b.real()),
+        (a, b) => a.decimal().partial_cmp(&b.decimal()),
+    }
+}
+
+/// Integers past 2^53 can't all be turned into reals, so whole reals are turned into integers
+/// instead where they can be.
+fn compare_exactly(integer: i64, real: f64) -> Option<Ordering> {
+    match real.fract() == 0.0 && real.abs() < 1e30 {
+        true => Some((integer as i128).cmp(&(real as i128))),
+        false => (integer as f64).partial_cmp(&real),
+    }
+}
+
+/// Writes a real so that it can be told apart from an integer, with a decimal point even when
+/// it's whole.
+pub(crate) fn write_real(f: &mut fmt::Formatter, real: f64) -> fmt::Result {
+    match real.is_finite() && real.fract() == 0.0 {
+        true => write!(f, "{:.1}", real),
+        false => write!(f, "{}", real),
+    }
+}
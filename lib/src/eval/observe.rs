@@ -0,0 +1,37 @@
+use super::{RuntimeError, Var};
+
+/// Something that watches a program while the Evaluator runs it, like a profiler or a grader.
+/// Every method does nothing unless it's implemented, so only the ones that are needed have to be.
+/// It goes wherever the Evaluator goes, so it has to be able to move to other threads.
+pub trait Observer: Send {
+    /// A statement on this line is about to be run.
+    fn statement_start(&mut self, _line: u32) {}
+
+    /// The statement on this line is done. Statements that call procedures are done once the
+    /// procedure returns, and the ones inside of them are done first.
+    fn statement_end(&mut self, _line: u32) {}
+
+    /// A variable is about to be given a new value. `old` is None if it didn't have one yet.
+    fn assign(&mut self, _name: &str, _old: Option<&Var>, _new: &Var) {}
+
+    /// A procedure, lambda or native is being called with these arguments.
+    fn call(&mut self, _procedure: &str, _args: &[Var]) {}
+
+    /// A procedure, lambda or native returned this value.
+    fn returned(&mut self, _procedure: &str, _value: &Var) {}
+
+    /// A procedure is RETURNing what the call it's about to make returns, so that call is taking
+    /// its place, and `returned` won't be called for it. The statement it was made in is done
+    /// once the call starts, rather than once it returns.
+    fn tail_call(&mut self, _procedure: &str) {}
+
+    /// An item in a list held by a variable was changed, rather than the variable being given a
+    /// whole new list.
+    fn list_mutation(&mut self, _name: &str, _list: &Var) {}
+
+    /// The program DISPLAYed this text.
+    fn output(&mut self, _text: &str) {}
+
+    /// Something went wrong, and the program is stopping.
+    fn error(&mut self, _error: &RuntimeError) {}
+}
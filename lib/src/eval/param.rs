@@ -1,25 +1,42 @@
-use super::Var;
+use super::{ListMode, NumberMode, RuntimeError, Var};
 
-pub struct Parameters(pub Vec<Var>);
+/// The arguments a native procedure was called with,
+/// along with whatever called it, so that it can call the procedures it's given in turn.
+pub struct Parameters<'a>(pub Vec<Var>, pub &'a mut dyn Call);
+
+/// Something that can run procedures, like the Evaluator or the TreeWalker.
+pub trait Call {
+    /// Calls the procedure with the given arguments, returning whatever it returns.
+    fn call(&mut self, procedure: &Var, args: Vec<Var>) -> Result<Var, RuntimeError>;
+
+    /// A whole number from `low` to `high`, including both, for RANDOM.
+    fn random(&mut self, low: i64, high: i64) -> Result<i64, RuntimeError>;
+
+    /// How the arithmetic operators should treat numbers.
+    fn number_mode(&self) -> NumberMode;
+
+    /// Whether putting a list in another list copies it.
+    fn list_mode(&self) -> ListMode;
+}
 
 macro_rules! conversion_wrapper {
     ( $( ($fn_name:ident, $fn_wrapped:ident, $result:ident) $(,)? )* ) => {
         $(
         #[inline]
         pub fn $fn_name(&self) -> Result<Vec<$result>, String> {
-            Ok(self
+            self
                .0
                .iter()
                .fold(Ok(Vec::new()), |acc: Result<_, String>, x| {
                    let mut args = acc?;
                    args.push(x.$fn_wrapped()?);
                    Ok(args)
-               })?)
+               })
         })*
     };
 }
 
-impl Parameters {
+impl Parameters<'_> {
     #[rustfmt::skip]
     conversion_wrapper!(
         (numbers , number , f64     )
@@ -0,0 +1,41 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Where RANDOM gets its numbers from. It's a SplitMix64 generator, which only does integer math,
+/// so the same seed gives the same numbers on every platform, wasm included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Random {
+    pub(super) state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A generator with a different seed every time the program is run.
+    /// The seed comes from the standard library's hashers, because they're already seeded
+    /// randomly on the platforms that can do so, without the clock that some of them don't have.
+    pub fn unseeded() -> Self {
+        Self::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A whole number from `low` to `high`, including both. `low` can't be bigger than `high`.
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128 + 1) as u128;
+        // scaling the number down like this, rather than taking the remainder, keeps every
+        // possibility very nearly as likely as the others without having to throw any away.
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        (low as i128 + offset as i128) as i64
+    }
+}
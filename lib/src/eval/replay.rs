@@ -0,0 +1,313 @@
+use super::{context, Evaluator, Native, Parameters, RuntimeError, Var};
+use crate::Raw;
+use std::fmt;
+
+/// Everything a run got from outside of the program, in the order it got it, so that the run can
+/// be done again exactly the same way. That's the answers to its INPUTs, and the numbers RANDOM
+/// gave it.
+///
+/// It's saved as text, starting with a header line, and then one line for each event:
+///
+/// ```text
+/// spoodly replay 1
+/// input 2 "name?" "Ada"
+/// random 5 1 6 4
+/// ```
+///
+/// An `input` line has the line of the program INPUT was called on, its prompt, and the answer
+/// it got. A `random` line has the line RANDOM was called on, the lowest and highest numbers it
+/// was asked for, and the number it gave. Text is wrapped in double quotes, with `\"`, `\\`, `\n`,
+/// `\r` and `\t` standing in for the characters that can't be written out as they are.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay {
+    pub events: Vec<Event>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Input {
+        line: u32,
+        prompt: String,
+        answer: String,
+    },
+    Random {
+        line: u32,
+        low: i64,
+        high: i64,
+        value: i64,
+    },
+}
+
+/// Whether the Evaluator is writing down a Replay or following one.
+pub(super) enum Tape {
+    Recording(Replay),
+    /// The Replay, along with how many of its events have been used up.
+    Replaying(Replay, usize),
+}
+
+const HEADER: &str = "spoodly replay 1";
+
+impl Replay {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for event in &self.events {
+            out += &format!("{}\n", event);
+        }
+        out
+    }
+
+    /// Reads a Replay back from the text `to_text` makes, saying which line is wrong if it can't.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => return Err(format!("a replay has to start with \"{}\"", HEADER)),
+        }
+        let events = lines
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                Event::parse(line).ok_or_else(|| format!("line {} of the replay is broken", i + 1))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { events })
+    }
+}
+
+impl Event {
+    fn parse(line: &str) -> Option<Self> {
+        let (kind, rest) = line.trim().split_once(' ')?;
+        let (line, rest) = rest.split_once(' ')?;
+        let line = line.parse().ok()?;
+        match kind {
+            "input" => {
+                let (prompt, rest) = unquote(rest.trim_start())?;
+                let (answer, rest) = unquote(rest.trim_start())?;
+                rest.trim().is_empty().then_some(Event::Input {
+                    line,
+                    prompt,
+                    answer,
+                })
+            }
+            "random" => match rest.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [low, high, value] => Some(Event::Random {
+                    line,
+                    low: low.parse().ok()?,
+                    high: high.parse().ok()?,
+                    value: value.parse().ok()?,
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// What the program was doing when it made this event, for saying where a replay went wrong.
+    fn describe(&self) -> String {
+        match self {
+            Event::Input { line, prompt, .. } => format!("INPUT({:?}) on line {}", prompt, line),
+            Event::Random {
+                line, low, high, ..
+            } => format!("RANDOM({}, {}) on line {}", low, high, line),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input {
+                line,
+                prompt,
+                answer,
+            } => write!(f, "input {} {} {}", line, quote(prompt), quote(answer)),
+            Event::Random {
+                line,
+                low,
+                high,
+                value,
+            } => write!(f, "random {} {} {} {}", line, low, high, value),
+        }
+    }
+}
+
+/// Wraps text in double quotes, escaping the characters that can't be written out as they are.
+pub(super) fn quote(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Reads quoted text, like `quote` makes, off the front of the string, returning it along with whatever comes after.
+pub(super) fn unquote(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((out, &text[i + 2..])),
+            '\\' => out.push(match chars.next()?.1 {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                _ => return None,
+            }),
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+impl Evaluator {
+    /// Starts writing down every INPUT answer and RANDOM number the programs it runs get, until
+    /// `stop_tape` is called. Any Replay that was being followed is dropped.
+    pub fn start_recording(&mut self) {
+        self.tape = Some(Tape::Recording(Replay::default()));
+    }
+
+    /// Has INPUT and RANDOM give the programs it runs what they gave when the Replay was
+    /// recorded, instead of asking the Host or picking new numbers. If a program calls them
+    /// somewhere else, or with something else, than it did then, it's stopped with an error.
+    pub fn start_replay(&mut self, replay: Replay) {
+        self.tape = Some(Tape::Replaying(replay, 0));
+    }
+
+    /// Stops recording or replaying. While recording, the Replay that was written down is given
+    /// back; while replaying, it's the events that were never used.
+    pub fn stop_tape(&mut self) -> Option<Replay> {
+        match self.tape.take()? {
+            Tape::Recording(replay) => Some(replay),
+            Tape::Replaying(mut replay, used) => {
+                replay.events.drain(..used);
+                Some(replay)
+            }
+        }
+    }
+
+    /// Calls the INPUT native, unless the Replay has the answer, writing it down if it's recording.
+    pub(super) fn input(&mut self, f: &Native, args: Vec<Var>) -> Result<Var, RuntimeError> {
+        let prompt = context::input_prompt(&args)?;
+        if let Some(answer) = self.replay_input(&prompt)? {
+            return Ok(Var::Raw(Raw::Text(answer)));
+        }
+        let answer = f(Parameters(args, self))?;
+        if let Var::Raw(Raw::Text(text)) = &answer {
+            self.record_input(&prompt, text);
+        }
+        Ok(answer)
+    }
+
+    /// The answer INPUT gave when the Replay was recorded, if one's being followed.
+    pub(super) fn replay_input(&mut self, prompt: &str) -> Result<Option<String>, RuntimeError> {
+        let line = self.line();
+        let wanted = Event::Input {
+            line,
+            prompt: prompt.to_string(),
+            answer: String::new(),
+        };
+        match self.replayed(&wanted)? {
+            Some(Event::Input { answer, .. }) => Ok(Some(answer)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Writes down the answer INPUT got, if a Replay is being recorded.
+    pub(super) fn record_input(&mut self, prompt: &str, answer: &str) {
+        let line = self.line();
+        if let Some(Tape::Recording(replay)) = &mut self.tape {
+            replay.events.push(Event::Input {
+                line,
+                prompt: prompt.to_string(),
+                answer: answer.to_string(),
+            });
+        }
+    }
+
+    /// A number for RANDOM, from the Replay if there is one, and written down if it's recording.
+    pub(super) fn random_between(&mut self, low: i64, high: i64) -> Result<i64, RuntimeError> {
+        let line = self.line();
+        let mut event = Event::Random {
+            line,
+            low,
+            high,
+            value: 0,
+        };
+        if let Some(Event::Random { value, .. }) = self.replayed(&event)? {
+            // a replay that's been changed by hand could have anything in it.
+            if value < low || value > high {
+                return Err(RuntimeError::new(format!(
+                    "the program called {}, but the replay has it picking {}",
+                    event.describe(),
+                    value
+                )));
+            }
+            return Ok(value);
+        }
+
+        let picked = self.random.between(low, high);
+        if let (Some(Tape::Recording(replay)), Event::Random { value, .. }) =
+            (&mut self.tape, &mut event)
+        {
+            *value = picked;
+            replay.events.push(event);
+        }
+        Ok(picked)
+    }
+
+    /// Uses up the next event in the Replay that's being followed, making sure the program is
+    /// doing the same thing now as it was then. The answer or value in `wanted` is ignored.
+    fn replayed(&mut self, wanted: &Event) -> Result<Option<Event>, RuntimeError> {
+        let (replay, used) = match &mut self.tape {
+            Some(Tape::Replaying(replay, used)) => (replay, used),
+            _ => return Ok(None),
+        };
+        let next = match replay.events.get(*used) {
+            Some(next) => next,
+            None => {
+                return Err(RuntimeError::new(format!(
+                    "the replay is over, but the program called {}",
+                    wanted.describe()
+                )))
+            }
+        };
+        let same = match (wanted, next) {
+            (
+                Event::Input { line, prompt, .. },
+                Event::Input {
+                    line: l, prompt: p, ..
+                },
+            ) => line == l && prompt == p,
+            (
+                Event::Random {
+                    line, low, high, ..
+                },
+                Event::Random {
+                    line: l,
+                    low: lo,
+                    high: hi,
+                    ..
+                },
+            ) => line == l && low == lo && high == hi,
+            _ => false,
+        };
+        if !same {
+            return Err(RuntimeError::new(format!(
+                "the program called {}, but when the replay was recorded it called {}",
+                wanted.describe(),
+                next.describe()
+            )));
+        }
+        *used += 1;
+        Ok(Some(next.clone()))
+    }
+}
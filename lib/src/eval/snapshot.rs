@@ -0,0 +1,702 @@
+use super::{
+    list::Saved,
+    replay::{quote, unquote},
+    Context, Evaluator, Frame, List, ListMode, NumberMode, Overflow, Precision, Random, Scope, Var,
+};
+use crate::{
+    compile::{Chunk, Op, Slot},
+    Raw,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+const HEADER: &str = "spoodly snapshot 4";
+
+/// Everything a snapshot has to say about the procedures and lambdas it holds, collected as
+/// they're run into so that each one is only written out once, however many things share it.
+/// Lists are numbered the same way, so that the ones that are shared are still shared once
+/// they're read back.
+#[derive(Default)]
+struct Saver {
+    chunks: Vec<Arc<Chunk>>,
+    chunk_ids: HashMap<*const Chunk, usize>,
+    scopes: Vec<Arc<Scope>>,
+    scope_ids: HashMap<*const Scope, usize>,
+    list_ids: HashMap<*const (), usize>,
+}
+
+impl Evaluator {
+    /// Writes down the state of the Evaluator as text, so that `restore` can pick up from there
+    /// later, even in another process. That's its globals, the Scopes of the calls it's in the
+    /// middle of and of the lambdas it holds, its call stack and where each call is at, what's on
+    /// the stack, how many steps it's taken, where RANDOM is at, its NumberMode and ListMode, and
+    /// the INPUT it's waiting on, if it is.
+    ///
+    /// Natives are written down by name, since they can't be written down themselves.
+    /// The Evaluator doesn't hold on to anything it's DISPLAYed, so whatever was shown before the
+    /// snapshot stays with the Host, but a Debugger's `snapshot` keeps what it's collected.
+    /// Its limits, Observer and Replay aren't written down.
+    ///
+    /// Snapshots can be taken between runs, while a resumable run is waiting for INPUT, or while
+    /// a Debugger has the program stopped, which is between any two of its steps.
+    pub fn snapshot(&self) -> Result<String, String> {
+        let mut saver = Saver::default();
+        let mut body = String::new();
+
+        let mut globals: Vec<_> = self.context.map.iter().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        writeln!(body, "globals {}", globals.len()).unwrap();
+        for (name, var) in globals {
+            body += &quote(name);
+            saver.var(&mut body, var)?;
+            body.push('\n');
+        }
+
+        writeln!(body, "frames {}", self.frames.len()).unwrap();
+        for frame in &self.frames {
+            let chunk = saver.chunk(&frame.chunk);
+            let scope = frame.scope.as_ref().map(|scope| saver.scope(scope));
+            write!(body, "{} {} ", chunk, frame.ip).unwrap();
+            id(&mut body, scope);
+            writeln!(body, " {}", frame.stack).unwrap();
+        }
+
+        write!(body, "stack {}", self.stack.len()).unwrap();
+        for var in &self.stack {
+            saver.var(&mut body, var)?;
+        }
+        body.push('\n');
+
+        writeln!(body, "steps {}", self.steps).unwrap();
+        writeln!(body, "random {}", self.random.state).unwrap();
+        match &self.waiting {
+            Some(prompt) => writeln!(body, "waiting {}", quote(prompt)).unwrap(),
+            None => writeln!(body, "waiting -").unwrap(),
+        }
+        writeln!(body, "resumable {}", self.resumable).unwrap();
+        let NumberMode {
+            exact_decimals,
+            integer_bits,
+            overflow,
+            precision,
+        } = self.number_mode;
+        write!(body, "numbers {} ", exact_decimals).unwrap();
+        id(&mut body, integer_bits.map(|bits| bits as usize));
+        let overflow = match overflow {
+            Overflow::Error => "error",
+            Overflow::Wrap => "wrap",
+        };
+        let precision = match precision {
+            Precision::Half => "half",
+            Precision::Single => "single",
+            Precision::Double => "double",
+        };
+        writeln!(body, " {} {}", overflow, precision).unwrap();
+        match self.list_mode {
+            ListMode::Copy => writeln!(body, "lists copy").unwrap(),
+            ListMode::Reference => writeln!(body, "lists reference").unwrap(),
+        }
+
+        // what's in a Scope can lead to more Scopes, so they're filled in until there are no more.
+        let mut slots = String::new();
+        let mut i = 0;
+        while let Some(scope) = saver.scopes.get(i).cloned() {
+            let vars = scope.slots.lock().unwrap();
+            write!(slots, "{}", vars.len()).unwrap();
+            for var in vars.iter() {
+                match var {
+                    Some(var) => saver.var(&mut slots, var)?,
+                    None => slots += " -",
+                }
+            }
+            slots.push('\n');
+            i += 1;
+        }
+
+        let mut out = format!("{}\nchunks {}\n", HEADER, saver.chunks.len());
+        for chunk in &saver.chunks {
+            saver.write_chunk(&mut out, chunk);
+        }
+        writeln!(out, "scopes {}", saver.scopes.len()).unwrap();
+        for scope in &saver.scopes {
+            let parent = scope
+                .parent
+                .as_ref()
+                .map(|parent| saver.scope_ids[&Arc::as_ptr(parent)]);
+            id(&mut out, parent);
+            write!(out, " {}", scope.names.len()).unwrap();
+            for name in scope.names.iter() {
+                write!(out, " {}", quote(name)).unwrap();
+            }
+            out.push('\n');
+        }
+        out += &slots;
+        out += &body;
+        Ok(out)
+    }
+
+    /// Picks up from a snapshot, with the natives it used taken from the Context by name.
+    /// The snapshot's globals are put in the Context, over whatever it already had by those names.
+    /// If it was waiting for INPUT, it can be given the answer with `resume`, and if it was
+    /// stopped anywhere else, it can be picked up with `continue_run`.
+    /// Snapshots are checked as they're read, down to every local each procedure uses and every
+    /// value each instruction expects on the stack, so one that's been edited by hand is turned
+    /// away with an error rather than leaving the Evaluator to trip over it later.
+    pub fn restore(snapshot: &str, context: Context) -> Result<Self, String> {
+        let mut reader = Reader {
+            text: snapshot,
+            context: &context,
+            chunks: Vec::new(),
+            scopes: Vec::new(),
+            lists: HashMap::new(),
+            lambdas: Vec::new(),
+        };
+        match snapshot.lines().next() {
+            Some(HEADER) => reader.text = &snapshot[HEADER.len()..],
+            _ => return Err(format!("a snapshot has to start with \"{}\"", HEADER)),
+        }
+
+        reader.expect("chunks")?;
+        for _ in 0..reader.number::<usize>()? {
+            let chunk = reader.chunk()?;
+            reader.chunks.push(Arc::new(chunk));
+        }
+
+        reader.expect("scopes")?;
+        for _ in 0..reader.number::<usize>()? {
+            let parent = reader.optional(|reader| reader.scope_id())?;
+            let names: Arc<[String]> = reader.list(|reader| reader.text())?.into();
+            reader.scopes.push(Arc::new(Scope {
+                slots: Mutex::new(vec![None; names.len()]),
+                names,
+                parent,
+            }));
+        }
+        for i in 0..reader.scopes.len() {
+            let slots = reader.list(|reader| reader.optional(|reader| reader.var()))?;
+            if slots.len() != reader.scopes[i].names.len() {
+                return Err("a scope has the wrong number of slots".to_string());
+            }
+            *reader.scopes[i].slots.lock().unwrap() = slots;
+        }
+
+        reader.expect("globals")?;
+        let mut globals = Vec::new();
+        for _ in 0..reader.number::<usize>()? {
+            globals.push((reader.text()?, reader.var()?));
+        }
+
+        reader.expect("frames")?;
+        let mut frames = Vec::new();
+        for _ in 0..reader.number::<usize>()? {
+            frames.push(Frame {
+                // these are worked out once the NumberMode has been read.
+                literals: Vec::new().into(),
+                chunk: reader.chunk_id()?,
+                ip: reader.number()?,
+                scope: reader.optional(|reader| reader.scope_id())?,
+                stack: reader.number()?,
+            });
+        }
+
+        reader.expect("stack")?;
+        let stack = reader.list(|reader| reader.var())?;
+        reader.expect("steps")?;
+        let steps = reader.number()?;
+        reader.expect("random")?;
+        let random = Random {
+            state: reader.number()?,
+        };
+        reader.expect("waiting")?;
+        let waiting = reader.optional(|reader| reader.text())?;
+        reader.expect("resumable")?;
+        let resumable = reader.number()?;
+        reader.expect("numbers")?;
+        let number_mode = NumberMode {
+            exact_decimals: reader.number()?,
+            integer_bits: reader.optional(|reader| reader.number())?,
+            overflow: match reader.word()? {
+                "error" => Overflow::Error,
+                "wrap" => Overflow::Wrap,
+                word => return Err(format!("{} isn't a kind of overflow", word)),
+            },
+            precision: match reader.word()? {
+                "half" => Precision::Half,
+                "single" => Precision::Single,
+                "double" => Precision::Double,
+                word => return Err(format!("{} isn't a precision", word)),
+            },
+        };
+        reader.expect("lists")?;
+        let list_mode = match reader.word()? {
+            "copy" => ListMode::Copy,
+            "reference" => ListMode::Reference,
+            word => return Err(format!("{} isn't a list mode", word)),
+        };
+        if reader.word().is_ok() {
+            return Err("there's more after the end of the snapshot".to_string());
+        }
+
+        let mut bases: Vec<_> = frames.iter().map(|frame| frame.stack).collect();
+        bases.push(stack.len());
+        let broken = bases.windows(2).any(|pair| pair[0] > pair[1])
+            || frames.iter().any(|frame| frame.ip > frame.chunk.code.len())
+            || frames.iter().enumerate().any(|(i, frame)| {
+                // every call but the innermost is waiting on the value of the one inside of it,
+                // and so is one waiting for INPUT.
+                let waiting_on = match i + 1 < frames.len() || waiting.is_some() {
+                    true => match frame.ip.checked_sub(1).map(|ip| frame.chunk.code[ip]) {
+                        Some(Op::Call(..)) => 1,
+                        _ => return true,
+                    },
+                    false => 0,
+                };
+                let height = heights(&frame.chunk).and_then(|heights| heights[frame.ip]);
+                let sizes = scope_sizes(frame.scope.as_ref());
+                height.is_none_or(|height| bases[i + 1] - bases[i] + waiting_on < height)
+                    || !locals_fit(&frame.chunk, &sizes)
+            });
+        if broken || (waiting.is_some() && frames.is_empty()) {
+            return Err("the snapshot's call stack doesn't add up".to_string());
+        }
+        let lambdas = reader.lambdas.iter().all(|(chunk, scope)| {
+            let mut sizes = vec![chunk.locals.len()];
+            sizes.extend(scope_sizes(scope.as_ref()));
+            locals_fit(chunk, &sizes)
+        });
+        if !lambdas {
+            return Err("a procedure uses locals its scopes don't have".to_string());
+        }
+
+        let mut evaluator = Evaluator::new(context);
+        evaluator.number_mode = number_mode;
+        for frame in &mut frames {
+            frame.literals = evaluator.literals(&frame.chunk);
+        }
+        evaluator.context.map.extend(globals);
+        evaluator.frames = frames;
+        evaluator.stack = stack;
+        evaluator.steps = steps;
+        evaluator.random = random;
+        evaluator.resumable = resumable || waiting.is_some();
+        evaluator.waiting = waiting;
+        evaluator.list_mode = list_mode;
+        Ok(evaluator)
+    }
+}
+
+impl Saver {
+    fn chunk(&mut self, chunk: &Arc<Chunk>) -> usize {
+        if let Some(&id) = self.chunk_ids.get(&Arc::as_ptr(chunk)) {
+            return id;
+        }
+        // lambdas are written out before the Chunks they're in, so that they're there to be
+        // pointed to when the Chunks are read back.
+        for lambda in &chunk.lambdas {
+            self.chunk(lambda);
+        }
+        self.chunks.push(chunk.clone());
+        self.chunk_ids
+            .insert(Arc::as_ptr(chunk), self.chunks.len() - 1);
+        self.chunks.len() - 1
+    }
+
+    fn scope(&mut self, scope: &Arc<Scope>) -> usize {
+        if let Some(&id) = self.scope_ids.get(&Arc::as_ptr(scope)) {
+            return id;
+        }
+        if let Some(parent) = &scope.parent {
+            self.scope(parent);
+        }
+        self.scopes.push(scope.clone());
+        self.scope_ids
+            .insert(Arc::as_ptr(scope), self.scopes.len() - 1);
+        self.scopes.len() - 1
+    }
+
+    fn var(&mut self, out: &mut String, var: &Var) -> Result<(), String> {
+        match var {
+            Var::Raw(raw) => raw_value(out, raw),
+            // a list is written out in full the first time, and by its number after that. It's
+            // written just as it is, along with whether the lists inside might be shared with a
+            // copy, so that saving it doesn't change it.
+            Var::List(list) => match self.list_ids.get(&list.id()) {
+                Some(id) => write!(out, " r {}", id).unwrap(),
+                None => {
+                    let id = self.list_ids.len();
+                    self.list_ids.insert(list.id(), id);
+                    let Saved(items, nested_shared) = list.save();
+                    write!(out, " l {} {} {}", id, nested_shared, items.len()).unwrap();
+                    for item in items.iter() {
+                        self.var(out, item)?;
+                    }
+                }
+            },
+            Var::Compiled(chunk, scope) => {
+                let chunk = self.chunk(chunk);
+                let scope = scope.as_ref().map(|scope| self.scope(scope));
+                write!(out, " c {} ", chunk).unwrap();
+                id(out, scope);
+            }
+            Var::Function(name, _) => write!(out, " f {}", quote(name)).unwrap(),
+            Var::Lambda(..) => return Err("can't snapshot the TreeWalker's lambdas".to_string()),
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&self, out: &mut String, chunk: &Chunk) {
+        match &chunk.name {
+            Some(name) => out.push_str(&quote(name)),
+            None => out.push('-'),
+        }
+        write!(out, " {}\n  locals {}", chunk.arity, chunk.locals.len()).unwrap();
+        for local in chunk.locals.iter() {
+            write!(out, " {}", quote(local)).unwrap();
+        }
+        write!(out, "\n  names {}", chunk.names.len()).unwrap();
+        for name in &chunk.names {
+            write!(out, " {}", quote(name)).unwrap();
+        }
+        write!(out, "\n  constants {}", chunk.constants.len()).unwrap();
+        for constant in &chunk.constants {
+            raw_value(out, constant);
+        }
+        write!(out, "\n  lambdas {}", chunk.lambdas.len()).unwrap();
+        for lambda in &chunk.lambdas {
+            write!(out, " {}", self.chunk_ids[&Arc::as_ptr(lambda)]).unwrap();
+        }
+        write!(out, "\n  code {}", chunk.code.len()).unwrap();
+        for op in &chunk.code {
+            match *op {
+                Op::Constant(i) => write!(out, " const {}", i),
+                Op::Load(slot) => write!(out, " load {}", slot_text(slot)),
+                Op::Store(slot) => write!(out, " store {}", slot_text(slot)),
+                Op::Call(slot, argc) => write!(out, " call {} {}", slot_text(slot), argc),
+                Op::Lambda(i) => write!(out, " lambda {}", i),
+                Op::List(n) => write!(out, " list {}", n),
+                Op::Pop => write!(out, " pop"),
+                Op::Return => write!(out, " return"),
+                Op::Jump(to) => write!(out, " jump {}", to),
+                Op::JumpIf(to) => write!(out, " jumpif {}", to),
+                Op::JumpUnless(to) => write!(out, " jumpunless {}", to),
+                Op::Countdown(to) => write!(out, " countdown {}", to),
+            }
+            .unwrap();
+        }
+        write!(out, "\n  lines {}", chunk.lines.len()).unwrap();
+        for line in &chunk.lines {
+            write!(out, " {}", line).unwrap();
+        }
+        out.push('\n');
+    }
+}
+
+/// Writes down the index of a Chunk or Scope, or `-` if there isn't one.
+fn id(out: &mut String, id: Option<usize>) {
+    match id {
+        Some(id) => write!(out, "{}", id).unwrap(),
+        None => out.push('-'),
+    }
+}
+
+fn raw_value(out: &mut String, raw: &Raw) {
+    match raw {
+        // Rust writes floats out with as many digits as it takes to read back the same number.
+        Raw::Integer(i) => write!(out, " i {}", i),
+        Raw::Real(n) => write!(out, " n {}", n),
+        Raw::Decimal(d) => write!(out, " d {}", d),
+        Raw::Text(text) => write!(out, " t {}", quote(text)),
+        Raw::Bool(b) => write!(out, " b {}", b),
+    }
+    .unwrap();
+}
+
+/// How many values each instruction of a Chunk has on the stack under it, as long as the code is
+/// something the compiler could have made, and the value the Chunk ends with is at the end.
+/// Instructions that can't be reached don't have a height. None means some instruction would
+/// pop more than there is, or can be reached with different heights.
+fn heights(chunk: &Chunk) -> Option<Vec<Option<usize>>> {
+    let mut heights = vec![None; chunk.code.len() + 1];
+    let mut todo: Vec<(usize, usize)> = vec![(0, 0)];
+    while let Some((ip, height)) = todo.pop() {
+        match heights[ip] {
+            Some(seen) if seen == height => continue,
+            Some(_) => return None,
+            None => heights[ip] = Some(height),
+        }
+        let Some(&op) = chunk.code.get(ip) else {
+            match height {
+                0 => return None,
+                _ => continue,
+            }
+        };
+        let (pops, pushes) = match op {
+            Op::Constant(_) | Op::Load(_) | Op::Lambda(_) => (0, 1),
+            Op::Store(_) | Op::Pop | Op::JumpIf(_) | Op::JumpUnless(_) | Op::Return => (1, 0),
+            Op::Call(_, n) | Op::List(n) => (n as usize, 1),
+            Op::Jump(_) => (0, 0),
+            Op::Countdown(_) => (1, 1),
+        };
+        let after = height.checked_sub(pops)? + pushes;
+        match op {
+            Op::Jump(to) => todo.push((to as usize, after)),
+            Op::JumpIf(to) | Op::JumpUnless(to) => {
+                todo.extend([(to as usize, after), (ip + 1, after)])
+            }
+            Op::Countdown(to) => todo.extend([(to as usize, after - 1), (ip + 1, after)]),
+            Op::Return => {}
+            _ => todo.push((ip + 1, after)),
+        }
+    }
+    Some(heights)
+}
+
+/// How many slots each Scope has, starting from this one and going outwards.
+fn scope_sizes(mut scope: Option<&Arc<Scope>>) -> Vec<usize> {
+    let mut sizes = Vec::new();
+    while let Some(current) = scope {
+        sizes.push(current.names.len());
+        scope = current.parent.as_ref();
+    }
+    sizes
+}
+
+/// Whether every local the Chunk and its lambdas use is there, when it's run in Scopes of these
+/// sizes.
+fn locals_fit(chunk: &Chunk, sizes: &[usize]) -> bool {
+    let fits = |op: &Op| match *op {
+        Op::Load(Slot::Local(depth, i))
+        | Op::Store(Slot::Local(depth, i))
+        | Op::Call(Slot::Local(depth, i), _) => sizes
+            .get(depth as usize)
+            .is_some_and(|&size| (i as usize) < size),
+        _ => true,
+    };
+    chunk.code.iter().all(fits)
+        && chunk.lambdas.iter().all(|lambda| {
+            let mut sizes = sizes.to_vec();
+            sizes.insert(0, lambda.locals.len());
+            locals_fit(lambda, &sizes)
+        })
+}
+
+fn slot_text(slot: Slot) -> String {
+    match slot {
+        Slot::Local(depth, index) => format!("local {} {}", depth, index),
+        Slot::Free(name, depth) => format!("free {} {}", name, depth),
+    }
+}
+
+/// Reads a snapshot back a word at a time, keeping track of the Chunks, Scopes and Lists it's made.
+struct Reader<'a> {
+    text: &'a str,
+    context: &'a Context,
+    chunks: Vec<Arc<Chunk>>,
+    scopes: Vec<Arc<Scope>>,
+    lists: HashMap<usize, List>,
+    /// The procedures and lambdas that have been read, with the Scopes they were defined in, so
+    /// that the locals they use can be checked once the Scopes are all there.
+    lambdas: Vec<(Arc<Chunk>, Option<Arc<Scope>>)>,
+}
+
+impl Reader<'_> {
+    /// The next word, which can't be quoted text.
+    fn word(&mut self) -> Result<&str, String> {
+        let text = self.text.trim_start();
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        let (word, rest) = text.split_at(end);
+        if word.is_empty() || word.starts_with('"') {
+            return Err("the snapshot ends too soon, or has something out of place".to_string());
+        }
+        self.text = rest;
+        Ok(word)
+    }
+
+    fn expect(&mut self, wanted: &str) -> Result<(), String> {
+        match self.word()? {
+            word if word == wanted => Ok(()),
+            word => Err(format!("expected {} in the snapshot, not {}", wanted, word)),
+        }
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, String> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| format!("couldn't make sense of {} in the snapshot", word))
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        let (text, rest) = unquote(self.text.trim_start())
+            .ok_or_else(|| "expected quoted text in the snapshot".to_string())?;
+        self.text = rest;
+        Ok(text)
+    }
+
+    /// Something that might be `-` instead.
+    fn optional<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        match self.text.trim_start().strip_prefix('-') {
+            Some(rest) if rest.starts_with(char::is_whitespace) || rest.is_empty() => {
+                self.text = rest;
+                Ok(None)
+            }
+            _ => read(self).map(Some),
+        }
+    }
+
+    /// How many things there are, and then each of them.
+    fn list<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        (0..self.number::<usize>()?).map(|_| read(self)).collect()
+    }
+
+    fn chunk_id(&mut self) -> Result<Arc<Chunk>, String> {
+        let id: usize = self.number()?;
+        (self.chunks.get(id).cloned()).ok_or_else(|| format!("there's no chunk {}", id))
+    }
+
+    fn scope_id(&mut self) -> Result<Arc<Scope>, String> {
+        let id: usize = self.number()?;
+        (self.scopes.get(id).cloned()).ok_or_else(|| format!("there's no scope {}", id))
+    }
+
+    /// Lists aren't read back in the order they were written, so one can be pointed to before
+    /// its items are read. It's made empty, and filled in when they are.
+    fn list_id(&mut self) -> Result<List, String> {
+        let id: usize = self.number()?;
+        Ok(self
+            .lists
+            .entry(id)
+            .or_insert_with(|| List::new(Vec::new()))
+            .clone())
+    }
+
+    fn raw(&mut self, kind: &str) -> Result<Raw, String> {
+        Ok(match kind {
+            "i" => Raw::Integer(self.number()?),
+            "n" => Raw::Real(self.number()?),
+            "d" => Raw::Decimal(self.number()?),
+            "t" => Raw::Text(self.text()?),
+            "b" => Raw::Bool(self.number()?),
+            _ => return Err(format!("{} isn't a kind of value", kind)),
+        })
+    }
+
+    fn var(&mut self) -> Result<Var, String> {
+        let kind = self.word()?.to_string();
+        Ok(match kind.as_str() {
+            "l" => {
+                let list = self.list_id()?;
+                let nested_shared = self.number()?;
+                let items = self.list(|reader| reader.var())?;
+                list.set(Saved(Arc::new(items), nested_shared));
+                Var::List(list)
+            }
+            "r" => Var::List(self.list_id()?),
+            "c" => {
+                let chunk = self.chunk_id()?;
+                let scope = self.optional(|reader| reader.scope_id())?;
+                self.lambdas.push((chunk.clone(), scope.clone()));
+                Var::Compiled(chunk, scope)
+            }
+            "f" => {
+                let name = self.text()?;
+                let native = self.context.map.get(&name).into_iter();
+                let native = (native.chain(self.context.map.values()))
+                    .find(|var| matches!(var, Var::Function(n, _) if **n == *name));
+                native
+                    .cloned()
+                    .ok_or_else(|| format!("there's no native called {} to restore", name))?
+            }
+            kind => Var::Raw(self.raw(kind)?),
+        })
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, String> {
+        let name = self.optional(|reader| reader.text())?;
+        let arity = self.number()?;
+        self.expect("locals")?;
+        let locals = self.list(|reader| reader.text())?.into();
+        self.expect("names")?;
+        let names = self.list(|reader| reader.text())?;
+        self.expect("constants")?;
+        let constants = self.list(|reader| {
+            let kind = reader.word()?.to_string();
+            reader.raw(&kind)
+        })?;
+        self.expect("lambdas")?;
+        let lambdas = self.list(|reader| reader.chunk_id())?;
+        self.expect("code")?;
+        let code = self.list(|reader| reader.op())?;
+        self.expect("lines")?;
+        let lines = self.list(|reader| reader.number())?;
+
+        let chunk = Chunk {
+            code,
+            lines,
+            constants,
+            names,
+            name,
+            locals,
+            arity,
+            lambdas,
+        };
+        // locals can't be checked without knowing which Scope the Chunk is run in, but everything
+        // else the instructions point to has to be there.
+        let fits = |op: &Op| match *op {
+            Op::Constant(i) => (i as usize) < chunk.constants.len(),
+            Op::Lambda(i) => (i as usize) < chunk.lambdas.len(),
+            Op::Load(Slot::Free(i, _))
+            | Op::Store(Slot::Free(i, _))
+            | Op::Call(Slot::Free(i, _), _) => (i as usize) < chunk.names.len(),
+            Op::Jump(to) | Op::JumpIf(to) | Op::JumpUnless(to) | Op::Countdown(to) => {
+                to as usize <= chunk.code.len()
+            }
+            _ => true,
+        };
+        let fits = chunk.code.iter().all(fits) && heights(&chunk).is_some();
+        match chunk.code.len() == chunk.lines.len()
+            && chunk.arity as usize <= chunk.locals.len()
+            && fits
+        {
+            true => Ok(chunk),
+            false => Err("a chunk's instructions point to things it doesn't have".to_string()),
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        let op = self.word()?.to_string();
+        Ok(match op.as_str() {
+            "const" => Op::Constant(self.number()?),
+            "load" => Op::Load(self.slot()?),
+            "store" => Op::Store(self.slot()?),
+            "call" => Op::Call(self.slot()?, self.number()?),
+            "lambda" => Op::Lambda(self.number()?),
+            "list" => Op::List(self.number()?),
+            "pop" => Op::Pop,
+            "return" => Op::Return,
+            "jump" => Op::Jump(self.number()?),
+            "jumpif" => Op::JumpIf(self.number()?),
+            "jumpunless" => Op::JumpUnless(self.number()?),
+            "countdown" => Op::Countdown(self.number()?),
+            op => return Err(format!("{} isn't an instruction", op)),
+        })
+    }
+
+    fn slot(&mut self) -> Result<Slot, String> {
+        let kind = self.word()?.to_string();
+        Ok(match kind.as_str() {
+            "local" => Slot::Local(self.number()?, self.number()?),
+            "free" => Slot::Free(self.number()?, self.number()?),
+            kind => return Err(format!("{} isn't a kind of slot", kind)),
+        })
+    }
+}
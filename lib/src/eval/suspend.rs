@@ -0,0 +1,79 @@
+use super::{Evaluator, RuntimeError, Var};
+use crate::{ast::Ast, compile::compile, Raw};
+use std::sync::Arc;
+
+/// How far a program got before handing control back to the host.
+pub enum Progress {
+    /// The program is over, and this is the value it ended with.
+    Finished(Var),
+    /// The program called INPUT with this prompt, and is waiting to be resumed with the answer.
+    NeedsInput(String),
+}
+
+impl Evaluator {
+    /// Runs the AST like `eval`, except that calling INPUT stops the program instead of asking the
+    /// Host, so that the answer can come in whenever it's ready and be passed to `resume`.
+    /// INPUTs inside of procedures that natives call, like the ones passed to MAP, can't stop the
+    /// program, so the Host is asked for those as usual.
+    pub fn run_resumable(&mut self, ast: Ast) -> Result<Progress, RuntimeError> {
+        let chunk = Arc::new(compile(&ast)?);
+        let depth = self.start(chunk);
+        self.resumable = true;
+        let result = self.proceed(depth, None);
+        self.progress(result)
+    }
+
+    /// Picks the program back up, with the INPUT it stopped at giving back this text.
+    pub fn resume(&mut self, input: impl Into<String>) -> Result<Progress, RuntimeError> {
+        let prompt = match self.waiting.take() {
+            Some(prompt) => prompt,
+            None => return Err(RuntimeError::new("the program isn't waiting for input")),
+        };
+        let input = input.into();
+        self.record_input(&prompt, &input);
+        let input = Var::Raw(Raw::Text(input));
+        // the answer counts toward the memory limits like any other text the program gets.
+        if let Err(e) = self.measure(&input) {
+            let stack = self.frames[0].stack;
+            let result = self.settle(0, stack, Err(e));
+            return self.progress(result);
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.returned("INPUT", &input);
+        }
+        self.stack.push(input);
+        let result = self.proceed(0, None);
+        self.progress(result)
+    }
+
+    /// Picks up a program that was stopped somewhere other than at an INPUT, like one restored
+    /// from a snapshot taken while a Debugger had it stopped.
+    pub fn continue_run(&mut self) -> Result<Progress, RuntimeError> {
+        if self.frames.is_empty() || self.waiting.is_some() {
+            return Err(RuntimeError::new(
+                "the program isn't stopped partway through",
+            ));
+        }
+        let result = self.proceed(0, None);
+        self.progress(result)
+    }
+
+    /// The prompt of the INPUT the program is stopped at, if it is.
+    pub fn waiting_for_input(&self) -> Option<&str> {
+        self.waiting.as_deref()
+    }
+
+    /// What to tell the host once a run has stopped, for whatever reason.
+    fn progress(
+        &mut self,
+        result: Result<Option<Var>, RuntimeError>,
+    ) -> Result<Progress, RuntimeError> {
+        match (result, self.waiting.clone()) {
+            (Ok(None), Some(prompt)) => Ok(Progress::NeedsInput(prompt)),
+            (result, _) => {
+                self.resumable = false;
+                result.map(|var| Progress::Finished(var.expect("paused without being asked to")))
+            }
+        }
+    }
+}
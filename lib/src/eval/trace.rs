@@ -0,0 +1,135 @@
+use super::{Evaluator, RuntimeError, Statements, Var};
+use crate::{ast::Ast, compile::compile};
+use std::{collections::HashSet, sync::Arc};
+
+/// What each of a program's variables held after every statement it ran, like the trace tables
+/// students fill in by hand.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceTable {
+    /// The variables, in the order they first showed up.
+    pub columns: Vec<String>,
+    pub rows: Vec<TraceRow>,
+}
+
+/// A statement that was run, and what each variable held once it was done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRow {
+    pub line: u32,
+    /// Lined up with the columns, but leaving off the ones that showed up later. Variables that
+    /// didn't have a value yet, or that can't be seen from where the statement was, are None.
+    pub values: Vec<Option<String>>,
+}
+
+impl Evaluator {
+    /// Runs the AST, filling in a row of a trace table each time a statement is done. Only the
+    /// program's own variables go in the table, not the globals that were around before it ran,
+    /// or its procedures. If something goes wrong, the table still has everything up until then.
+    pub fn trace_table(&mut self, ast: Ast) -> (TraceTable, Result<Var, RuntimeError>) {
+        let mut table = TraceTable::default();
+        let chunk = match compile(&ast) {
+            Ok(chunk) => Arc::new(chunk),
+            Err(e) => return (table, Err(e.into())),
+        };
+        let host: HashSet<String> = self.context.map.keys().cloned().collect();
+        let mut statements = Statements::default();
+
+        let depth = self.start(chunk);
+        let mut watch = |evaluator: &mut Evaluator| {
+            let evaluator = &*evaluator;
+            statements.advance(&evaluator.frames, |line| table.row(line, evaluator, &host));
+            false
+        };
+        let result = self
+            .proceed(depth, Some(&mut watch))
+            .map(|var| var.expect("paused without being asked to"));
+
+        if result.is_ok() {
+            statements.finish(|line| table.row(line, self, &host));
+        }
+        (table, result)
+    }
+}
+
+impl TraceTable {
+    /// Adds a row with the variables that can be seen from wherever the Evaluator is.
+    fn row(&mut self, line: u32, evaluator: &Evaluator, host: &HashSet<String>) {
+        let scope = evaluator
+            .frames
+            .last()
+            .and_then(|frame| frame.scope.as_ref());
+        let mut values = vec![None; self.columns.len()];
+        for (name, var) in evaluator.variables(scope) {
+            if host.contains(&name) || !matches!(var, Var::Raw(_) | Var::List(_)) {
+                continue;
+            }
+            let column = match self.columns.iter().position(|column| *column == name) {
+                Some(column) => column,
+                None => {
+                    self.columns.push(name);
+                    values.push(None);
+                    self.columns.len() - 1
+                }
+            };
+            values[column] = Some(var.to_string());
+        }
+        self.rows.push(TraceRow { line, values });
+    }
+
+    /// The header, and then every row, with each cell filled in.
+    fn grid(&self) -> Vec<Vec<String>> {
+        let header = std::iter::once("Line".to_string()).chain(self.columns.iter().cloned());
+        let rows = self.rows.iter().map(|row| {
+            let values = (0..self.columns.len())
+                .map(|i| row.values.get(i).cloned().flatten().unwrap_or_default());
+            std::iter::once(row.line.to_string())
+                .chain(values)
+                .collect()
+        });
+        std::iter::once(header.collect()).chain(rows).collect()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        for (i, row) in self.grid().into_iter().enumerate() {
+            let cells: Vec<_> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+            out += &format!("| {} |\n", cells.join(" | "));
+            if i == 0 {
+                out += &format!("|{}\n", " --- |".repeat(cells.len()));
+            }
+        }
+        out
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        for row in self.grid() {
+            let cells: Vec<_> = (row.iter())
+                .map(|cell| match cell.contains(&[',', '"', '\n'][..]) {
+                    true => format!("\"{}\"", cell.replace('"', "\"\"")),
+                    false => cell.clone(),
+                })
+                .collect();
+            out += &cells.join(",");
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut out = "<table>\n".to_string();
+        for (i, row) in self.grid().into_iter().enumerate() {
+            let tag = if i == 0 { "th" } else { "td" };
+            out += "<tr>";
+            for cell in row {
+                let cell = (cell.replace('&', "&amp;"))
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+                    .replace('"', "&quot;");
+                out += &format!("<{0}>{1}</{0}>", tag, cell);
+            }
+            out += "</tr>\n";
+        }
+        out += "</table>\n";
+        out
+    }
+}
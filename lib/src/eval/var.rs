@@ -1,65 +1,113 @@
-use super::{Parameters, Raw};
-use crate::parse::Node;
-use std::fmt;
+use super::{
+    number::{compare, write_real},
+    walk::Env,
+    walk::Procedure,
+    List, Parameters, Raw, RuntimeError, Scope,
+};
+use crate::compile::Chunk;
+use std::{cmp::Ordering, fmt, ptr, sync::Arc};
+
+/// A procedure provided by the host. When something goes wrong, it returns an error to stop the
+/// program with. They have to be safe to call from any thread, so that one Context can be shared
+/// by Evaluators running all over the place.
+pub type Native = dyn Fn(Parameters) -> Result<Var, RuntimeError> + Send + Sync;
 
 /// A value that can be manipulated.
 /// These tend to be stored in Contexts.
 /// They can enter programs from Literals embedded in the source, as the result of calculations,
 /// and from being accessed from the standard library where any number of them may be stored.
-/// Under the current implementation, having functions act as variables is really annoying
-/// and make a number of things more difficult than they should be.
+/// Procedures are values too, so they can be stored in variables and lists and passed around
+/// like any other.
+#[derive(Clone)]
 pub enum Var {
     Raw(Raw),
-    List(Vec<Var>),
-    Lambda(Node),
-    Function(Box<dyn Fn(Parameters) -> Var>),
+    List(List),
+    /// A lambda or procedure for the TreeWalker to run,
+    /// along with the variables around where it was defined.
+    Lambda(Arc<Procedure>, Option<Arc<Env>>),
+    /// A lambda whose body has been compiled, so that the Evaluator can run it,
+    /// along with the Scope it was defined in.
+    Compiled(Arc<Chunk>, Option<Arc<Scope>>),
+    /// A procedure provided by the host, and the name it goes by.
+    Function(Arc<str>, Arc<Native>),
 }
 impl fmt::Display for Var {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use Raw::*;
+        write_var(f, self, &mut Vec::new())
+    }
+}
 
-        match self {
-            Var::Raw(r) => match r {
-                Text(t) => write!(f, "\"{}\"", t),
-                Number(n) => write!(f, "{}", n),
-                Bool(b) => write!(f, "{}", b),
-            },
-            Var::List(l) => {
-                write!(f, "[")?;
-                for (i, var) in l.iter().enumerate() {
-                    match i {
-                        0 => write!(f, "{}", var)?,
-                        _ => write!(f, ", {}", var)?,
-                    }
+/// Writes a value out, knowing which lists it's inside of, so that a list a host has put inside
+/// of itself is written as `[...]` there, rather than over and over forever.
+fn write_var(f: &mut fmt::Formatter<'_>, var: &Var, inside: &mut Vec<*const ()>) -> fmt::Result {
+    use Raw::*;
+
+    match var {
+        Var::Raw(r) => match r {
+            Text(t) => write!(f, "\"{}\"", t),
+            Integer(i) => write!(f, "{}", i),
+            Real(r) => write_real(f, *r),
+            Decimal(d) => write!(f, "{}", d),
+            Bool(b) => write!(f, "{}", b),
+        },
+        Var::List(l) if inside.contains(&l.id()) => write!(f, "[...]"),
+        Var::List(l) => {
+            inside.push(l.id());
+            write!(f, "[")?;
+            for (i, var) in l.peek().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
                 }
-                write!(f, "]")
+                write_var(f, var, inside)?;
             }
-            _ => write!(f, "You can't print functions yet!"),
+            inside.pop();
+            write!(f, "]")
         }
+        // procedures are printed by name, lambdas don't have one.
+        Var::Lambda(procedure, _) => write_name(f, procedure.name.as_deref()),
+        Var::Compiled(chunk, _) => write_name(f, chunk.name.as_deref()),
+        Var::Function(name, _) => write!(f, "{}", name),
+    }
+}
+
+fn write_name(f: &mut fmt::Formatter<'_>, name: Option<&str>) -> fmt::Result {
+    match name {
+        Some(name) => write!(f, "{}", name),
+        None => write!(f, "<lambda>"),
     }
 }
+
 impl Var {
-    /// Yields the result of calling the function if the variable is one, and a String explaining
-    /// that it isn't a function if it isn't.
-    #[inline]
-    pub fn fn_call(&self, args: Vec<Var>) -> Result<Var, String> {
-        use Var::*;
+    /// Wraps a Rust closure up so that it can be called like any other procedure.
+    pub fn native(
+        name: &str,
+        f: impl Fn(Parameters) -> Result<Var, RuntimeError> + Send + Sync + 'static,
+    ) -> Var {
+        Var::Function(name.into(), Arc::new(f))
+    }
 
+    /// A new list holding the items.
+    pub fn list(items: Vec<Var>) -> Var {
+        Var::List(List::new(items))
+    }
+
+    /// The value, but with a copy of the list if it's a list, rather than the same one.
+    pub fn copied(&self) -> Var {
         match self {
-            Raw(_) => Err(format!("{} isn't a function!", self)),
-            List(_) => Err(format!("Can't call list {}!", self)),
-            Lambda(_) => Err(format!("Can't call lambda {} with fn_call!", self)),
-            Function(f) => Ok((*f)(Parameters(args))),
+            Var::List(list) => Var::List(list.copy()),
+            var => var.clone(),
         }
     }
 
     /// Returns a number if the given variable can be turned into one, and a message explaining why
-    /// if it can't.
+    /// if it can't. Integers and Decimals are turned into reals.
     #[inline]
     pub fn number(&self) -> Result<f64, String> {
         match self {
             Var::Raw(r) => match r {
-                Raw::Number(n) => Ok(*n),
+                Raw::Integer(i) => Ok(*i as f64),
+                Raw::Real(n) => Ok(*n),
+                Raw::Decimal(d) => Ok(d.to_real()),
                 Raw::Text(_) => Err("Can't coerce Text into number".to_string()),
                 Raw::Bool(_) => Err("Can't coerce Bool into number".to_string()),
             },
@@ -70,11 +118,8 @@ impl Var {
     #[inline]
     pub fn string(&self) -> Result<String, String> {
         match self {
-            Var::Raw(r) => Ok(match r {
-                Raw::Number(n) => format!("{}", n),
-                Raw::Bool(b) => format!("{}", b),
-                Raw::Text(t) => t.to_string(),
-            }),
+            Var::Raw(Raw::Text(t)) => Ok(t.to_string()),
+            Var::Raw(_) => Ok(self.to_string()),
             _ => Err("Can't parse functions into numbers".to_string()),
         }
     }
@@ -83,11 +128,106 @@ impl Var {
     pub fn boolean(&self) -> Result<bool, String> {
         match self {
             Var::Raw(r) => match r {
-                Raw::Number(_) => Err("Can't turn Number into bool".to_string()),
+                Raw::Integer(_) | Raw::Real(_) | Raw::Decimal(_) => {
+                    Err("Can't turn Number into bool".to_string())
+                }
                 Raw::Text(_) => Err("Can't turn Text into bool".to_string()),
                 Raw::Bool(b) => Ok(*b),
             },
             _ => Err("Can't parse functions into booleans".to_string()),
         }
     }
+
+    /// What kind of value this is, for saying which two things can't be compared.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Var::Raw(Raw::Integer(_) | Raw::Real(_) | Raw::Decimal(_)) => "a number",
+            Var::Raw(Raw::Text(_)) => "text",
+            Var::Raw(Raw::Bool(_)) => "a boolean",
+            Var::List(_) => "a list",
+            Var::Lambda(..) | Var::Compiled(..) | Var::Function(..) => "a procedure",
+        }
+    }
+
+    /// Whether two values are the same, which is what `=` does. Numbers are equal when they're
+    /// worth the same, whatever kind of number they are, and lists are equal when they hold equal
+    /// items in the same order. Procedures are only equal to themselves, not to procedures that
+    /// do the same thing. Values of different kinds, like a number and text, can't be compared.
+    pub fn equals(&self, other: &Var) -> Result<bool, String> {
+        self.equals_inside(other, &mut Vec::new())
+    }
+
+    /// `equals`, knowing which pairs of lists are already being compared further out. Coming
+    /// across one of them again means a list is inside of itself, and nothing in it differs along
+    /// the way there, so that pair counts as equal rather than being compared forever.
+    fn equals_inside(
+        &self,
+        other: &Var,
+        comparing: &mut Vec<(*const (), *const ())>,
+    ) -> Result<bool, String> {
+        Ok(match (self, other) {
+            (Var::Raw(Raw::Text(a)), Var::Raw(Raw::Text(b))) => a == b,
+            (Var::Raw(Raw::Bool(a)), Var::Raw(Raw::Bool(b))) => a == b,
+            (Var::Raw(a), Var::Raw(b)) if self.kind() == other.kind() => {
+                compare(a, b) == Some(Ordering::Equal)
+            }
+            (Var::List(a), Var::List(b)) if comparing.contains(&(a.id(), b.id())) => true,
+            (Var::List(a), Var::List(b)) => {
+                comparing.push((a.id(), b.id()));
+                let (a, b) = (a.peek(), b.peek());
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (a, b) in a.iter().zip(b.iter()) {
+                    if !a.equals_inside(b, comparing)? {
+                        return Ok(false);
+                    }
+                }
+                comparing.pop();
+                true
+            }
+            (Var::Lambda(a, a_env), Var::Lambda(b, b_env)) => {
+                Arc::ptr_eq(a, b) && same(a_env.as_ref(), b_env.as_ref())
+            }
+            (Var::Compiled(a, a_scope), Var::Compiled(b, b_scope)) => {
+                Arc::ptr_eq(a, b) && same(a_scope.as_ref(), b_scope.as_ref())
+            }
+            (Var::Function(_, a), Var::Function(_, b)) => {
+                ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b))
+            }
+            _ if self.kind() == other.kind() => false,
+            _ => return Err(mismatch(self, other)),
+        })
+    }
+
+    /// Which of two values comes first, which is what `<` and `>` go by. Numbers go by what
+    /// they're worth, and text goes in alphabetical order, or rather the order of the characters
+    /// in Unicode, so capital letters come before lowercase ones. None if one of them isn't a
+    /// number at all, which is never bigger or smaller than anything.
+    pub fn order(&self, other: &Var) -> Result<Option<Ordering>, String> {
+        match (self, other) {
+            (Var::Raw(Raw::Text(a)), Var::Raw(Raw::Text(b))) => Ok(Some(a.cmp(b))),
+            (Var::Raw(a), Var::Raw(b))
+                if self.kind() == "a number" && other.kind() == "a number" =>
+            {
+                Ok(compare(a, b))
+            }
+            _ if self.kind() == other.kind() => {
+                Err("Only numbers and text can be put in order!".to_string())
+            }
+            _ => Err(mismatch(self, other)),
+        }
+    }
+}
+
+/// Whether both are the same scope, or there's no scope for either of them.
+fn same<T>(a: Option<&Arc<T>>, b: Option<&Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+fn mismatch(a: &Var, b: &Var) -> String {
+    format!("Can't compare {} with {}!", a.kind(), b.kind())
 }
@@ -0,0 +1,426 @@
+use super::{
+    arity_error, recursion_error, Call, CancelHandle, Context, ListMode, NumberMode, Parameters,
+    Random, RuntimeError, StackDepth, TraceFrame, Var, RECURSION_LIMIT,
+};
+use crate::ast::{Ast, Control, Node};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// A TreeWalker evaluates an Abstract Syntax Tree directly, by recursively walking through it.
+/// It's much slower than the Evaluator, but it's so simple that it's kept around as a reference
+/// for how programs should behave.
+/// Every call a program makes takes up a good deal of the Rust stack, so the program is stopped
+/// once its calls use up more than STACK_LIMIT of it, even if they're under the recursion limit.
+/// Calls that are `RETURN`ed straight away don't, since they take the place of the call they're
+/// in, like they do in the Evaluator.
+pub struct TreeWalker {
+    /// Where globals are kept, along with everything the host provides.
+    context: Context,
+    /// The line of source code that's being run.
+    line: u32,
+    /// The name of each procedure or lambda that's being called, with the line it was called from.
+    calls: Vec<(String, u32)>,
+    /// How many calls can be going at once before the program is stopped.
+    recursion_limit: usize,
+    /// How much of the Rust stack the calls that are going use up.
+    stack_depth: StackDepth,
+    /// How many nodes a run can evaluate before it's stopped, if there's a limit at all.
+    step_limit: Option<u64>,
+    /// How many nodes the current run has evaluated so far.
+    steps: u64,
+    cancel: CancelHandle,
+    random: Random,
+    number_mode: NumberMode,
+    list_mode: ListMode,
+}
+
+/// A procedure or lambda for the TreeWalker to run.
+pub struct Procedure {
+    /// Lambdas don't have names, only procedures do.
+    pub(crate) name: Option<String>,
+    parameters: Vec<String>,
+    body: Node,
+    /// The line it was defined on, which is where its body starts.
+    line: u32,
+}
+
+/// The variables of one call to a procedure or lambda, for the TreeWalker.
+/// The lambdas defined during the call hold on to it, so that they can still get at its variables
+/// once it has returned.
+pub struct Env {
+    map: Mutex<HashMap<String, Var>>,
+    /// The Env the procedure or lambda was defined in, or None if it was defined at the top.
+    parent: Option<Arc<Env>>,
+}
+
+impl TreeWalker {
+    pub fn new(context: Context) -> Self {
+        Self {
+            context,
+            line: 1,
+            calls: Vec::new(),
+            recursion_limit: RECURSION_LIMIT,
+            stack_depth: StackDepth::default(),
+            step_limit: None,
+            steps: 0,
+            cancel: CancelHandle::default(),
+            random: Random::unseeded(),
+            number_mode: NumberMode::default(),
+            list_mode: ListMode::default(),
+        }
+    }
+
+    /// Changes how deep procedures can call each other before the program is stopped.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// Limits how many nodes each run can evaluate, so that programs which never end get stopped.
+    /// The TreeWalker counts nodes rather than instructions, so it doesn't take as many steps as
+    /// the Evaluator does for the same program. None, the default, lets them run for as long as
+    /// they like.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// How many nodes the last run evaluated, or the current one has evaluated so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// A handle that stops the TreeWalker when it's cancelled, which can be sent to other threads.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Makes RANDOM give the same numbers in the same order as any other TreeWalker, or Evaluator,
+    /// with the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    /// Changes how arithmetic treats numbers, for every run from now on.
+    pub fn set_number_mode(&mut self, mode: NumberMode) {
+        self.number_mode = mode;
+    }
+
+    /// Changes whether giving a variable a list copies it, for every run from now on.
+    pub fn set_list_mode(&mut self, mode: ListMode) {
+        self.list_mode = mode;
+    }
+
+    /// Runs the given AST. May manipulate the Context stored in the TreeWalker.
+    pub fn eval(&mut self, ast: Ast) -> Result<Var, RuntimeError> {
+        self.line = 1;
+        self.steps = 0;
+        match self.run(ast, None) {
+            Ok(var) | Err(Unwind::Return(var)) => Ok(var),
+            Err(Unwind::Error(e)) => Err(e),
+            Err(Unwind::TailCall(..)) => unreachable!("the program can't make tail calls"),
+        }
+    }
+
+    fn run(&mut self, ast: Ast, env: Option<&Arc<Env>>) -> Result<Var, Unwind> {
+        let vars = self.walk(ast.into_iter().rev().collect(), env)?;
+        Ok(fold(vars, self.list_mode))
+    }
+
+    /// Evaluates the nodes in the given AST, last to first, returning the values they produced.
+    /// Calls itself recursively to evaluate arbitrarily nested blocks.
+    fn walk(&mut self, mut ast: Ast, env: Option<&Arc<Env>>) -> Result<Vec<Var>, Unwind> {
+        let mut vars = Vec::new();
+
+        while let Some(node) = ast.pop() {
+            self.step()
+                .and_then(|()| self.node(node, env, &mut vars))
+                .map_err(|e| self.locate(e))?;
+        }
+        Ok(vars)
+    }
+
+    /// Counts a node towards the step limit, stopping the program if it's gone over it or if it's
+    /// been cancelled.
+    fn step(&mut self) -> Result<(), Unwind> {
+        self.steps += 1;
+        if self.step_limit.is_some_and(|limit| self.steps > limit) {
+            return Err(RuntimeError::new("step limit exceeded").into());
+        }
+        if self.cancel.is_cancelled() {
+            return Err(RuntimeError::new("cancelled").into());
+        }
+        Ok(())
+    }
+
+    /// Evaluates a single node, adding whatever values it produces to the ones passed in.
+    fn node(
+        &mut self,
+        node: Node,
+        env: Option<&Arc<Env>>,
+        vars: &mut Vec<Var>,
+    ) -> Result<(), Unwind> {
+        if let Some(control) = node.control() {
+            return self.control(control, env);
+        }
+
+        match node {
+            // Blocks don't get a scope of their own, like the spec says; only procedure calls do.
+            Node::Block(children) | Node::List(children) => {
+                vars.push(self.run(children, env)?);
+            }
+            Node::Assign(id, val_node) => {
+                let mut to = self.run(vec![*val_node], env)?;
+                if self.list_mode == ListMode::Copy {
+                    to = to.copied();
+                }
+                self.assign(env, id, to);
+            }
+            Node::Call(id, args) => {
+                let args = self.walk(args.into_iter().rev().collect(), env)?;
+                let callee = self.fetch(env, &id)?;
+                vars.push(self.invoke(&callee, args)?);
+            }
+            Node::Lambda(ast) => {
+                let procedure = Procedure {
+                    name: None,
+                    parameters: vec![],
+                    body: *ast,
+                    line: self.line,
+                };
+                vars.push(Var::Lambda(Arc::new(procedure), env.cloned()));
+            }
+            Node::Value(raw) => {
+                let raw = self.number_mode.literal(raw).map_err(RuntimeError::new)?;
+                vars.push(Var::Raw(raw));
+            }
+            Node::Var(id) => vars.push(self.fetch(env, &id)?),
+            Node::Line(line) => self.line = line,
+        }
+        Ok(())
+    }
+
+    /// Runs a control structure, which doesn't produce any values.
+    fn control(&mut self, control: Control, env: Option<&Arc<Env>>) -> Result<(), Unwind> {
+        match control {
+            Control::If(condition, then, otherwise) => {
+                if self.run(vec![condition.clone()], env)?.boolean()? {
+                    self.run(vec![then.clone()], env)?;
+                } else if let Some(otherwise) = otherwise {
+                    self.run(vec![otherwise.clone()], env)?;
+                }
+            }
+            Control::RepeatTimes(count, body) => {
+                let mut count = self.run(vec![count.clone()], env)?.number()?;
+                while count >= 1.0 {
+                    self.run(vec![body.clone()], env)?;
+                    count -= 1.0;
+                }
+            }
+            Control::RepeatUntil(condition, body) => {
+                // the condition is checked on the line the loop starts on, wherever the body ended.
+                let line = self.line;
+                while !self.run(vec![condition.clone()], env)?.boolean()? {
+                    self.run(vec![body.clone()], env)?;
+                    self.line = line;
+                }
+            }
+            Control::Procedure(name, parameters, body) => {
+                let parameters = parameters
+                    .iter()
+                    .filter_map(|parameter| match parameter {
+                        Node::Var(id) => Some(id.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let procedure = Procedure {
+                    name: Some(name.to_string()),
+                    parameters,
+                    body: body.clone(),
+                    line: self.line,
+                };
+                let procedure = Var::Lambda(Arc::new(procedure), env.cloned());
+                self.assign(env, name.to_string(), procedure);
+            }
+            // the program itself has nothing to take the place of, and a call with the wrong number
+            // of arguments is left to fail from where it was made.
+            Control::Return(Node::Call(id, args)) if !self.calls.is_empty() => {
+                let args = self.walk(args.iter().rev().cloned().collect(), env)?;
+                let callee = self.fetch(env, id)?;
+                return match callee {
+                    Var::Lambda(ref procedure, _) if procedure.parameters.len() == args.len() => {
+                        Err(Unwind::TailCall(callee, args))
+                    }
+                    callee => Err(Unwind::Return(self.invoke(&callee, args)?)),
+                };
+            }
+            Control::Return(value) => {
+                let value = self.run(vec![value.clone()], env)?;
+                return Err(Unwind::Return(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls a procedure, returning whatever it returns.
+    fn invoke(&mut self, callee: &Var, args: Vec<Var>) -> Result<Var, Unwind> {
+        let outermost = self.stack_depth.enter().map_err(RuntimeError::new)?;
+        let result = self.run_call(callee, args);
+        self.stack_depth.leave(outermost);
+        result
+    }
+
+    /// Runs a call, and then every call it's replaced by with a tail call.
+    fn run_call(&mut self, callee: &Var, args: Vec<Var>) -> Result<Var, Unwind> {
+        let (mut callee, mut args) = (callee.clone(), args);
+        loop {
+            let (procedure, captured) = match &callee {
+                Var::Function(_, f) => return Ok(f(Parameters(args, self))?),
+                Var::Lambda(procedure, captured) => (procedure.clone(), captured.clone()),
+                _ => return Err(RuntimeError::new("can't call that").into()),
+            };
+            let parameters = &procedure.parameters;
+            if parameters.len() != args.len() {
+                return Err(arity_error(parameters.len(), args.len()).into());
+            }
+            if self.calls.len() >= self.recursion_limit {
+                return Err(RuntimeError::new(recursion_error(self.recursion_limit)).into());
+            }
+
+            // lambdas get a new scope each time they're called, which their parameters are put in.
+            // Outside of it, they see the variables around where they were defined.
+            let new = Arc::new(Env {
+                map: Mutex::new(parameters.iter().cloned().zip(args).collect()),
+                parent: captured,
+            });
+            let name = procedure.name.as_deref().unwrap_or("<lambda>").to_string();
+            let caller = std::mem::replace(&mut self.line, procedure.line);
+            self.calls.push((name, caller));
+            let result = self.run(vec![procedure.body.clone()], Some(&new));
+            self.calls.pop();
+            self.line = caller;
+            match result {
+                Ok(var) | Err(Unwind::Return(var)) => return Ok(var),
+                Err(Unwind::TailCall(next, next_args)) => (callee, args) = (next, next_args),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Says where an error happened, if it doesn't know yet.
+    fn locate(&self, unwind: Unwind) -> Unwind {
+        match unwind {
+            Unwind::Error(e) => Unwind::Error(e.at(self.line).traced(|| self.trace())),
+            unwind => unwind,
+        }
+    }
+
+    /// Where each of the calls that are going is at, innermost first.
+    fn trace(&self) -> Vec<TraceFrame> {
+        let mut line = self.line;
+        let mut trace = Vec::new();
+        for (name, called_from) in self.calls.iter().rev() {
+            trace.push(TraceFrame {
+                procedure: Some(name.clone()),
+                line,
+            });
+            line = *called_from;
+        }
+        trace.push(TraceFrame {
+            procedure: None,
+            line,
+        });
+        trace
+    }
+
+    /// Searches through a given Env, all of its ancestors, and then the Context for a variable.
+    fn fetch(&self, mut env: Option<&Arc<Env>>, id: &str) -> Result<Var, String> {
+        while let Some(current) = env {
+            if let Some(var) = current.map.lock().unwrap().get(id) {
+                return Ok(var.clone());
+            }
+            env = current.parent.as_ref();
+        }
+
+        self.context
+            .map
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("couldn't find variable with identifier {}", id))
+    }
+
+    /// Updates the closest variable with that name, or makes a new one in the most local Env
+    /// if there aren't any. Globals are only updated from outside of any procedure, which is
+    /// where variables are kept in the Context.
+    fn assign(&mut self, env: Option<&Arc<Env>>, id: String, to: Var) {
+        let mut search = env;
+        while let Some(current) = search {
+            if let Some(var) = current.map.lock().unwrap().get_mut(&id) {
+                *var = to;
+                return;
+            }
+            search = current.parent.as_ref();
+        }
+
+        match env {
+            Some(env) => {
+                env.map.lock().unwrap().insert(id, to);
+            }
+            None => {
+                self.context.map.insert(id, to);
+            }
+        }
+    }
+}
+
+impl Call for TreeWalker {
+    fn call(&mut self, procedure: &Var, args: Vec<Var>) -> Result<Var, RuntimeError> {
+        match self.invoke(procedure, args) {
+            Ok(var) | Err(Unwind::Return(var)) => Ok(var),
+            Err(Unwind::Error(e)) => Err(e),
+            Err(Unwind::TailCall(..)) => unreachable!("tail calls are made by invoke"),
+        }
+    }
+
+    fn random(&mut self, low: i64, high: i64) -> Result<i64, RuntimeError> {
+        Ok(self.random.between(low, high))
+    }
+
+    fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+
+    fn list_mode(&self) -> ListMode {
+        self.list_mode
+    }
+}
+
+/// Why the TreeWalker stopped walking through a node before it was done.
+enum Unwind {
+    /// A `RETURN` is leaving the procedure it's in with this value.
+    Return(Var),
+    /// A `RETURN` is leaving the procedure it's in to call this one with these arguments, so that
+    /// the call takes its place instead of going inside of it.
+    TailCall(Var, Vec<Var>),
+    Error(RuntimeError),
+}
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+impl From<String> for Unwind {
+    fn from(e: String) -> Self {
+        Unwind::Error(e.into())
+    }
+}
+
+/// Only one value is returned as-is, any other number of them are returned together in a List,
+/// which gets copies of the lists among them unless lists are shared.
+fn fold(mut vars: Vec<Var>, mode: ListMode) -> Var {
+    match vars.len() {
+        1 => vars.pop().unwrap(),
+        _ if mode == ListMode::Copy => Var::list(vars.iter().map(Var::copied).collect()),
+        _ => Var::list(vars),
+    }
+}
@@ -3,47 +3,28 @@ mod token;
 pub use token::Token;
 
 pub fn tokenize<S: Into<String>>(source: S) -> Result<Vec<Token>, String> {
+    Ok(tokenize_lines(source)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect())
+}
+
+/// Like tokenize, but each token comes with the number of the line it was found on,
+/// starting from 1. The blocks around each line are found on that line too.
+pub fn tokenize_lines<S: Into<String>>(source: S) -> Result<Vec<(Token, u32)>, String> {
     use Token::*;
 
-    let mut tokens: Vec<Token> = vec![BlockOpen, BlockOpen];
     let source = source.into();
     let mut chars = source.chars().peekable();
+    let mut number = 1;
 
-    let mut read_until_depths = Vec::new();
-    let mut bracket_depth = 0;
-
-    // I want variadic push
-    macro_rules! token_push_internal {
-        ( $(,)* ) => {};
-        (BlockOpen, $($tail:tt)*) => {
-            bracket_depth += 1;
-            tokens.push(BlockOpen);
-            token_push!($($tail)*);
-        };
-        (BlockClose, $($tail:tt)*) => {
-            if let Some(depth) = read_until_depths.last() {
-                //println!("There's a depth to read until");
-                if *depth == bracket_depth {
-                    //println!("It's the depth we're reading until the end of");
-                    read_until_depths.pop();
-                    tokens.push(BlockClose);
-                }
-            }
-            tokens.push(BlockClose);
-
-            #[allow(unused_assignments)]
-            bracket_depth -= 1;
+    // Every curly brace opens a new frame that collects the lines inside of it,
+    // the outermost frame being the program itself.
+    let mut frames = vec![Frame::new(number)];
 
-            token_push!($($tail)*);
-        };
-        ($name:expr, $($tail:tt)*) => {
-            tokens.push($name);
-            token_push!($($tail)*);
-        };
-    }
     macro_rules! token_push {
-        ( $($tail:tt)* ) => {
-            { token_push_internal!($($tail)*,); }
+        ( $($token:expr),* $(,)? ) => {
+            { $( frames.last_mut().unwrap().line.push(($token, number)); )* }
         };
     }
 
@@ -52,24 +33,30 @@ pub fn tokenize<S: Into<String>>(source: S) -> Result<Vec<Token>, String> {
             '<' => match chars.peek() {
                 Some('-') => {
                     chars.next();
-                    token_push!(StorageArrow);
-
-                    read_until_depths.push(bracket_depth);
-                    tokens.push(BlockOpen);
+                    frames.last_mut().unwrap().store(number);
                 }
                 _ => token_push!(BinaryOperation(c.to_string())),
             },
+            '←' => frames.last_mut().unwrap().store(number),
 
             '|' => token_push!(LambdaStart),
 
             '\n' => {
-                token_push!(BlockClose, BlockOpen);
+                frames.last_mut().unwrap().end_line(number);
+                number += 1;
+            }
+            '{' => frames.push(Frame::new(number)),
+            '}' => {
+                let block = frames.pop().unwrap().close(number);
+                match frames.last_mut() {
+                    Some(frame) => frame.line.extend(block),
+                    None => return Err("Unmatched closing curly brace".to_string()),
+                }
             }
-            '{' => token_push!(BlockOpen),
-            '}' => token_push!(BlockClose),
             '(' => token_push!(ArgsOpen),
             ')' => token_push!(ArgsClose),
             '+' | '-' | '*' | '/' | '=' | '>' => token_push!(BinaryOperation(c.to_string())),
+            ',' => {}
             c => {
                 if c.is_whitespace() {
                     // do nothing
@@ -78,7 +65,10 @@ pub fn tokenize<S: Into<String>>(source: S) -> Result<Vec<Token>, String> {
 
                     if c != '"' {
                         while let Some(fc) = chars.peek() {
-                            if fc.is_alphanumeric() {
+                            // only numbers get to have a decimal point in them.
+                            if fc.is_alphanumeric()
+                                || (*fc == '.' && name.chars().all(|c| c.is_ascii_digit()))
+                            {
                                 name.push(chars.next().unwrap())
                             } else {
                                 break;
@@ -96,14 +86,21 @@ pub fn tokenize<S: Into<String>>(source: S) -> Result<Vec<Token>, String> {
 
                     if c == '"' {
                         // pushing it if it's a string literal.
-                        if !(chars.next() == Some('"')) {
+                        if chars.next() != Some('"') {
                             return Err("Unfinished string literal".to_string());
                         }
                         name.remove(0);
                         token_push!(StringLiteral(name));
                     } else if let Ok(n) = name.parse() {
-                        // numbers
-                        token_push!(Number(n));
+                        token_push!(Integer(n));
+                    } else if name.chars().all(|c| c.is_ascii_digit()) {
+                        // numbers without a decimal point are integers, so they have to fit in one.
+                        return Err(format!(
+                            "line {}: {} is too big for an integer",
+                            number, name
+                        ));
+                    } else if let Ok(n) = name.parse() {
+                        token_push!(Real(n));
                     } else {
                         match name.as_ref() {
                             "MOD" | "AND" | "OR" => token_push!(BinaryOperation(name.to_string())),
@@ -118,90 +115,174 @@ pub fn tokenize<S: Into<String>>(source: S) -> Result<Vec<Token>, String> {
         }
     }
 
-    token_push!(BlockClose, BlockClose);
+    if frames.len() > 1 {
+        return Err("Unclosed curly brace".to_string());
+    }
+
+    // the program always gets a block per line, even if there's only one.
+    let mut program = frames.pop().unwrap();
+    program.end_line(number);
+    let mut tokens = vec![(BlockOpen, 1)];
+    for line in program.lines {
+        tokens.extend(Frame::line_block(line));
+    }
+    tokens.push((BlockClose, number));
     Ok(tokens)
 }
 
+/// The tokens found between a pair of curly braces, split up into lines.
+struct Frame {
+    /// The line the opening curly brace is on.
+    start: u32,
+    lines: Vec<Vec<(Token, u32)>>,
+    line: Vec<(Token, u32)>,
+    /// How many storage arrows on the current line are still waiting
+    /// for the end of the line to close the block holding their value.
+    arrows: usize,
+}
+impl Frame {
+    fn new(start: u32) -> Self {
+        Self {
+            start,
+            lines: Vec::new(),
+            line: Vec::new(),
+            arrows: 0,
+        }
+    }
+
+    /// Everything after a storage arrow goes in its own block,
+    /// which lasts until the end of the line.
+    fn store(&mut self, number: u32) {
+        self.line.push((Token::StorageArrow, number));
+        self.line.push((Token::BlockOpen, number));
+        self.arrows += 1;
+    }
+
+    fn end_line(&mut self, number: u32) {
+        for _ in 0..self.arrows {
+            self.line.push((Token::BlockClose, number));
+        }
+        self.arrows = 0;
+
+        if !self.line.is_empty() {
+            self.lines.push(std::mem::take(&mut self.line));
+        }
+    }
+
+    /// Turns the frame into a block. Blocks that fit on one line hold that line's tokens directly,
+    /// otherwise each line gets a block of its own.
+    fn close(mut self, number: u32) -> Vec<(Token, u32)> {
+        use Token::*;
+
+        self.end_line(number);
+        let mut tokens = vec![(BlockOpen, self.start)];
+        match self.lines.len() {
+            1 => tokens.extend(self.lines.pop().unwrap()),
+            _ => {
+                for line in self.lines {
+                    tokens.extend(Frame::line_block(line));
+                }
+            }
+        }
+        tokens.push((BlockClose, number));
+        tokens
+    }
+
+    /// Puts a line in a block of its own, found on the same line as the tokens inside of it.
+    fn line_block(line: Vec<(Token, u32)>) -> Vec<(Token, u32)> {
+        let (first, last) = (line[0].1, line[line.len() - 1].1);
+        let mut tokens = vec![(Token::BlockOpen, first)];
+        tokens.extend(line);
+        tokens.push((Token::BlockClose, last));
+        tokens
+    }
+}
+
 #[test]
 fn test_tokenize() {
     use Token::*;
 
-    assert_eq!(
-        tokenize("IF true { DISPLAY(\"hi\") }").unwrap(),
-        #[rustfmt::skip]
-        [
-            BlockOpen,
-            BlockOpen,
-                Identifier("IF".to_string()), Identifier("true".to_string()),
-                    BlockOpen,
-                        Identifier("DISPLAY".to_string()), ArgsOpen,
-                            StringLiteral("hi".to_string()),
-                        ArgsClose,
-                    BlockClose,
-            BlockClose,
-            BlockClose,
-        ]
-    );
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
+        BlockOpen,
+            Identifier("IF".to_string()), Identifier("true".to_string()),
+                BlockOpen,
+                    Identifier("DISPLAY".to_string()), ArgsOpen,
+                        StringLiteral("hi".to_string()),
+                    ArgsClose,
+                BlockClose,
+        BlockClose,
+        BlockClose,
+    ];
+    assert_eq!(tokenize("IF true { DISPLAY(\"hi\") }").unwrap(), expected);
 
-    assert_eq!(
-        tokenize("s <- 3").unwrap(),
-        #[rustfmt::skip]
-        [
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                    BlockClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
                 BlockClose,
             BlockClose,
-        ]
-    );
+        BlockClose,
+    ];
+    assert_eq!(tokenize("s <- 3").unwrap(), expected);
 
-    assert_eq!(
-        tokenize("s<-3").unwrap(),
-        #[rustfmt::skip]
-        [
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                    BlockClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
                 BlockClose,
             BlockClose,
-        ]
-    );
+        BlockClose,
+    ];
+    assert_eq!(tokenize("s<-3").unwrap(), expected);
 
-    assert_eq!(
-        tokenize("s <- 3 + 2").unwrap(),
-        #[rustfmt::skip]
-        [
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                        BinaryOperation("+".to_string()),
-                        Number(2.0),
-                    BlockClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
+                    BinaryOperation("+".to_string()),
+                    Integer(2),
                 BlockClose,
             BlockClose,
-        ]
-    );
-    assert_eq!(
-        tokenize("s<-3+2").unwrap(),
-        #[rustfmt::skip]
-        [
+        BlockClose,
+    ];
+    assert_eq!(tokenize("s <- 3 + 2").unwrap(), expected);
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                        BinaryOperation("+".to_string()),
-                        Number(2.0),
-                    BlockClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
+                    BinaryOperation("+".to_string()),
+                    Integer(2),
                 BlockClose,
             BlockClose,
-        ]
-    );
+        BlockClose,
+    ];
+    assert_eq!(tokenize("s<-3+2").unwrap(), expected);
 
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
+            BlockOpen,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
+                BlockClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("DISPLAY".to_string()), ArgsOpen,
+                    Identifier("s".to_string()),
+                ArgsClose,
+            BlockClose,
+        BlockClose,
+    ];
     assert_eq!(
         tokenize(
             "\
@@ -210,23 +291,24 @@ fn test_tokenize() {
         "
         )
         .unwrap(),
-        #[rustfmt::skip]
-        [
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                    BlockClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("DISPLAY".to_string()), ArgsOpen,
-                        Identifier("s".to_string()),
-                    ArgsClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
                 BlockClose,
             BlockClose,
-        ]
-    );
-
+            BlockOpen,
+                Identifier("DISPLAY".to_string()), ArgsOpen,
+                    Identifier("s".to_string()),
+                ArgsClose,
+            BlockClose,
+        BlockClose,
+    ];
     assert_eq!(
         tokenize(
             "\
@@ -235,23 +317,59 @@ fn test_tokenize() {
         "
         )
         .unwrap(),
-        #[rustfmt::skip]
-        [
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = vec![
+        BlockOpen,
             BlockOpen,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                    BlockClose,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Integer(3),
                 BlockClose,
-                BlockOpen,
-                    Identifier("DISPLAY".to_string()), ArgsOpen,
-                        Identifier("s".to_string()),
-                    ArgsClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("l".to_string()), StorageArrow, BlockOpen,
+                    Integer(4),
                 BlockClose,
             BlockClose,
-        ]
-    );
-
+            BlockOpen,
+                Identifier("a".to_string()), StorageArrow, BlockOpen,
+                    Integer(1),
+                BlockClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("s".to_string()), StorageArrow, BlockOpen,
+                    Identifier("a".to_string()), BinaryOperation("+".to_string()), Integer(5),
+                BlockClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("l".to_string()), StorageArrow, BlockOpen,
+                    Identifier("a".to_string()),
+                BlockClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("a".to_string()), StorageArrow, BlockOpen,
+                    Identifier("a".to_string()), BinaryOperation("+".to_string()), Integer(3),
+                BlockClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("DISPLAY".to_string()), ArgsOpen,
+                    Identifier("s".to_string()),
+                ArgsClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("DISPLAY".to_string()), ArgsOpen,
+                    Identifier("l".to_string()),
+                ArgsClose,
+            BlockClose,
+            BlockOpen,
+                Identifier("DISPLAY".to_string()), ArgsOpen,
+                    Identifier("a".to_string()),
+                ArgsClose,
+            BlockClose,
+        BlockClose,
+    ];
     assert_eq!(
         tokenize(
             "\
@@ -267,55 +385,55 @@ fn test_tokenize() {
         "
         )
         .unwrap(),
-        #[rustfmt::skip]
-        vec![
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
             BlockOpen,
+                Identifier("REPEAT".to_string()), Integer(2), Identifier("TIMES".to_string()),
                 BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Number(3.0),
-                    BlockClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("l".to_string()), StorageArrow, BlockOpen,
-                        Number(4.0),
-                    BlockClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("a".to_string()), StorageArrow, BlockOpen,
-                        Number(1.0),
-                    BlockClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("s".to_string()), StorageArrow, BlockOpen,
-                        Identifier("a".to_string()), BinaryOperation("+".to_string()), Number(5.0),
+                    BlockOpen,
+                        Identifier("DISPLAY".to_string()), ArgsOpen, Real(1.5), ArgsClose,
                     BlockClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("l".to_string()), StorageArrow, BlockOpen,
-                        Identifier("a".to_string()),
+                    BlockOpen,
+                        Identifier("DISPLAY".to_string()), ArgsOpen, Integer(2), ArgsClose,
                     BlockClose,
                 BlockClose,
-                BlockOpen,
-                    Identifier("a".to_string()), StorageArrow, BlockOpen,
-                        Identifier("a".to_string()), BinaryOperation("+".to_string()), Number(3.0),
+            BlockClose,
+        BlockClose,
+    ];
+    assert_eq!(
+        tokenize(
+            "\
+            REPEAT 2 TIMES {
+                DISPLAY(1.5)
+                DISPLAY(2)
+            }\
+        "
+        )
+        .unwrap(),
+        expected
+    );
+
+    #[rustfmt::skip]
+    let expected = [
+        BlockOpen,
+            BlockOpen,
+                Identifier("x".to_string()), StorageArrow, BlockOpen,
+                    LambdaStart,
+                    BlockOpen,
+                        Identifier("y".to_string()), StorageArrow, BlockOpen,
+                            Integer(1),
+                        BlockClose,
                     BlockClose,
                 BlockClose,
-                BlockOpen,
-                    Identifier("DISPLAY".to_string()), ArgsOpen,
-                        Identifier("s".to_string()),
-                    ArgsClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("DISPLAY".to_string()), ArgsOpen,
-                        Identifier("l".to_string()),
-                    ArgsClose,
-                BlockClose,
-                BlockOpen,
-                    Identifier("DISPLAY".to_string()), ArgsOpen,
-                        Identifier("a".to_string()),
-                    ArgsClose,
-                BlockClose,
             BlockClose,
-        ]
-    )
+        BlockClose,
+    ];
+    assert_eq!(tokenize("x ← | { y <- 1 }").unwrap(), expected);
+
+    assert!(tokenize("IF true { DISPLAY(1)").is_err());
+    assert!(tokenize("DISPLAY(1) }").is_err());
 }
@@ -8,6 +8,7 @@ pub enum Token {
     LambdaStart,
     BinaryOperation(String),
     StringLiteral(String),
-    Number(f64),
+    Integer(i64),
+    Real(f64),
     Identifier(String),
 }
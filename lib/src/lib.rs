@@ -1,25 +1,34 @@
-#![feature(stmt_expr_attributes)]
-
+pub mod compile;
 pub mod eval;
 pub mod lex;
 pub mod parse;
 
-pub use eval::{Context, Evaluator};
+pub use compile::compile;
+pub use eval::{CancelHandle, Context, Debugger, Evaluator, Host, RuntimeError, TreeWalker};
 pub use lex::tokenize;
 pub use parse::{ast, parse};
 
 /// Returns the Result (which might be an error!) of running the source String that's provided.
+/// Runtime errors come with a stack trace.
 /// # Panics:
 /// This shouldn't panic. It might panic. Optimally, errors are handled and returned as Error
 /// messages.
 pub fn interpret<S: Into<String>>(src: S, ctx: Context) -> Result<eval::Var, String> {
-    Evaluator::new(ctx).eval(vec![parse(src)?], 0)
+    Evaluator::new(ctx)
+        .eval(vec![parse(src)?])
+        .map_err(|e| format!("{:#}", e))
 }
 
 /// Raw values are stored as literals in program code, or used inside of variables.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Raw {
-    Number(f64),
+    /// A whole number, which arithmetic keeps exact.
+    Integer(i64),
+    /// A number that may have a fractional part, or that's too big to be an Integer.
+    Real(f64),
+    /// An exact number with a fractional part, which is only made when the NumberMode asks for
+    /// exact decimals.
+    Decimal(eval::Decimal),
     Text(String),
     Bool(bool),
 }
@@ -14,9 +14,58 @@ pub enum Node {
     // commands
     Assign(String, Box<Node>),
     Call(String, Vec<Node>),
+    /// Marks where the statements from a new line of source code start. Only blocks spanning
+    /// several lines have these; everything else is on the same line as the code around it.
+    Line(u32),
 }
 impl Node {
     pub fn new_block() -> Self {
         Node::Block(Vec::new())
     }
 }
+
+/// The control structures from the spec. The parser reads these like any other line starting with
+/// a word, so they show up as calls to a function named after their keyword.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Control<'a> {
+    /// `IF condition { ... }`, with the block to run otherwise if there's an `ELSE { ... }`.
+    If(&'a Node, &'a Node, Option<&'a Node>),
+    /// `REPEAT n TIMES { ... }`
+    RepeatTimes(&'a Node, &'a Node),
+    /// `REPEAT UNTIL condition { ... }`
+    RepeatUntil(&'a Node, &'a Node),
+    /// `PROCEDURE name(parameters) { ... }`, where every parameter is a Var.
+    Procedure(&'a str, &'a [Node], &'a Node),
+    /// `RETURN(value)`
+    Return(&'a Node),
+}
+impl Node {
+    /// Returns the control structure this Node represents, if it's one of them.
+    pub fn control(&self) -> Option<Control<'_>> {
+        let (name, args) = match self {
+            Node::Call(name, args) => (name, args),
+            _ => return None,
+        };
+
+        use Node::{Call, Lambda, Var};
+        match (name.as_ref(), args.as_slice()) {
+            ("IF", [condition, Lambda(then)]) => Some(Control::If(condition, then, None)),
+            ("IF", [condition, Lambda(then), Var(word), Lambda(otherwise)]) if word == "ELSE" => {
+                Some(Control::If(condition, then, Some(otherwise)))
+            }
+            ("REPEAT", [count, Var(word), Lambda(body)]) if word == "TIMES" => {
+                Some(Control::RepeatTimes(count, body))
+            }
+            ("REPEAT", [Var(word), condition, Lambda(body)]) if word == "UNTIL" => {
+                Some(Control::RepeatUntil(condition, body))
+            }
+            ("PROCEDURE", [Call(name, parameters), Lambda(body)])
+                if parameters.iter().all(|p| matches!(p, Var(_))) =>
+            {
+                Some(Control::Procedure(name, parameters, body))
+            }
+            ("RETURN", [value]) => Some(Control::Return(value)),
+            _ => None,
+        }
+    }
+}
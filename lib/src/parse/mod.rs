@@ -2,158 +2,256 @@
 /// The Tree is comprised of Nodes, which could represent function calls, literal values, or
 /// variable identifiers.
 pub mod ast;
-pub use ast::{Ast, Node};
+pub use ast::{Ast, Control, Node};
 
 use super::{lex::Token, Raw};
 
+/// Identifiers that start a control structure. These never name a function being called,
+/// even when they're followed by parentheses, as in `IF (a = b)`.
+const KEYWORDS: &[&str] = &["IF", "ELSE", "REPEAT", "TIMES", "UNTIL", "PROCEDURE"];
+
 /// A parser takes a sequence of tokens and turns them into an Abstract Syntax Tree.
 struct Parser {
-    tokens: Vec<Token>,
+    /// The tokens left to parse, the next one last, each with the line it was found on.
+    tokens: Vec<(Token, u32)>,
+    /// The line the last token parsed was found on.
+    line: u32,
 }
 impl Parser {
     /// When creating a new Parser, you pass in the tokens you'd like for it to parse.
-    fn new(tokens: Vec<Token>) -> Self {
-        let tokens = tokens.iter().rev().map(|x| x.clone()).collect();
-        //eprintln!("{:?}", tokens);
-        Self { tokens }
+    fn new(tokens: Vec<(Token, u32)>) -> Self {
+        let tokens = tokens.into_iter().rev().collect();
+        Self { tokens, line: 1 }
     }
 
-    /// Depletes the series of tokens stored internally, turning them into commands
-    /// that can be stored in the AST that's passed in.
-    fn parse(&mut self, mut ast: Ast) -> Result<Ast, String> {
-        if let Some(token) = self.tokens.pop() {
-            //eprintln!("got {:?}", token);
-            match token {
-                Token::BlockOpen | Token::ArgsOpen => {
-                    let mut block_nodes = Ast::new();
-                    loop {
-                        let token = self
-                            .tokens
-                            .last()
-                            .ok_or("blocks ain't supposed to close like that".to_string())?;
-                        match token {
-                            // remove the block then leave.
-                            Token::BlockClose | Token::ArgsClose => {
-                                self.tokens.pop();
-                                break;
-                            }
-                            _ => {
-                                block_nodes = self.parse(block_nodes)?;
-                            }
-                        }
-                    }
-                    match token {
-                        Token::BlockOpen => ast.push(Node::Block(block_nodes)),
-                        Token::ArgsOpen => ast.push(Node::List(block_nodes)),
-                        _ => {}
-                    }
-                }
-                Token::Identifier(a) => {
-                    match self
-                        .tokens
-                        .last()
-                        .ok_or("something's gotta follow an identifier".to_string())?
-                    {
-                        // if a storage arrow comes after the identifier,
-                        // they're trying to assign the variable to a new value.
-                        Token::StorageArrow => {
-                            // remove the storage arrow because who would want that
-                            self.tokens.pop();
-                            // grab the thing after the arrow
-                            let next_node = self
-                                .parse(Ast::new())?
-                                .pop()
-                                .ok_or("arrow left us hangin'".to_string())?;
-                            // push a new Assign Node into the AST where
-                            // the Var we've found is assigned to the next_node.
-                            //eprintln!("adding assign");
-                            ast.push(Node::Assign(a, Box::new(next_node)));
-                        }
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.last().map(|(token, _)| token)
+    }
 
-                        // if a new block follows the identifier,
-                        // it must be a function call.
-                        Token::ArgsOpen => {
-                            println!("fn");
-                            ast.push(Node::Call(
-                                a,
-                                match self
-                                    .parse(Ast::new())?
-                                    .pop()
-                                    .ok_or("no args for function".to_string())?
-                                {
-                                    Node::List(items) => items,
-                                    _ => {
-                                        return Err("Only Lists can be used as function arguments"
-                                            .to_string())
-                                    }
-                                },
-                            ));
-                        }
+    fn next(&mut self) -> Option<Token> {
+        let (token, line) = self.tokens.pop()?;
+        self.line = line;
+        Some(token)
+    }
 
-                        // if neither of these follow an identifier,
-                        // it must just be a reference to a variable.
-                        _ => {
-                            println!("returning a variable!");
-                            ast.push(Node::Var(a));
-                        }
-                    }
-                }
-                Token::LambdaStart => {
-                    // remove the lambda start
-                    self.tokens.pop();
+    /// Parses the contents of a block, up to and including the token that closes it.
+    /// Blocks spanning several lines hold a block per line, while blocks that fit on one line hold
+    /// that line's tokens directly.
+    fn block(&mut self) -> Result<Ast, String> {
+        if self.peek() != Some(&Token::BlockOpen) {
+            // a block holding a single line still says where it is, if it's not where it opened.
+            let opened = self.line;
+            let line = self.tokens.last().map_or(opened, |&(_, line)| line);
+            let statements = self.line()?;
+            let new_line = Some(line).filter(|&line| line != opened && !statements.is_empty());
+            return Ok(new_line
+                .map(Node::Line)
+                .into_iter()
+                .chain(statements)
+                .collect());
+        }
 
-                    // get the block that comes after the lambda start
-                    let next_node = self
-                        .parse(Ast::new())?
-                        .pop()
-                        .ok_or("no block after lambda".to_string())?;
+        let opened = self.line;
+        let mut statements = Ast::new();
+        while self.peek() == Some(&Token::BlockOpen) {
+            self.next();
+            let line = self.line;
+            let mut new_line = Some(line).filter(|&line| line != opened);
+            for statement in self.line()? {
+                push_statement(&mut statements, statement, new_line.take());
+            }
+        }
+        match self.next() {
+            Some(Token::BlockClose) => Ok(statements),
+            _ => Err("blocks ain't supposed to close like that".to_string()),
+        }
+    }
 
-                    ast.push(Node::Lambda(Box::new(next_node)));
+    /// Parses everything on one line, up to and including the token that closes it,
+    /// and then splits all of that up into statements.
+    fn line(&mut self) -> Result<Ast, String> {
+        let mut items = Ast::new();
+        loop {
+            match self.peek() {
+                Some(Token::BlockClose) => {
+                    self.next();
+                    return Ok(statements(items));
                 }
-                Token::StringLiteral(s) => {
-                    ast.push(Node::Value(Raw::Text(s)));
+                Some(_) => self.item(&mut items)?,
+                None => return Err("blocks ain't supposed to close like that".to_string()),
+            }
+        }
+    }
+
+    /// Parses the arguments passed to a function, up to and including the closing parenthesis.
+    fn args(&mut self) -> Result<Ast, String> {
+        let mut items = Ast::new();
+        loop {
+            match self.peek() {
+                Some(Token::ArgsClose) => {
+                    self.next();
+                    return Ok(items);
                 }
-                Token::Number(n) => {
-                    ast.push(Node::Value(Raw::Number(n)));
+                Some(_) => self.item(&mut items)?,
+                None => return Err("parentheses ain't supposed to close like that".to_string()),
+            }
+        }
+    }
+
+    /// Parses the next thing in the sequence of tokens and adds it to the items passed in.
+    /// Binary operations take the item before them out and put themselves in its place.
+    fn item(&mut self, items: &mut Ast) -> Result<(), String> {
+        let token = self.next().ok_or_else(|| "ran out of tokens".to_string())?;
+        match token {
+            Token::BlockOpen => items.push(Node::Block(self.block()?)),
+            Token::ArgsOpen => items.push(Node::List(self.args()?)),
+            Token::Identifier(a) => match self.peek() {
+                // if a storage arrow comes after the identifier,
+                // they're trying to assign the variable to a new value.
+                Some(Token::StorageArrow) => {
+                    self.next();
+                    let value = self.operand("arrow left us hangin'")?;
+                    items.push(Node::Assign(a, Box::new(unwrap_block(value))));
                 }
-                Token::BinaryOperation(op_name) => {
-                    let left = ast.pop().ok_or("add what dude?".to_string())?;
-                    ast.push(Node::Call(
-                        op_name.clone(),
-                        vec![
-                            left,
-                            self.parse(Ast::new())?
-                                .pop()
-                                .ok_or(&format!("can't {} nothing", op_name))?,
-                        ],
-                    ));
+
+                // if a list of arguments follows the identifier,
+                // it must be a function call.
+                Some(Token::ArgsOpen) if !KEYWORDS.contains(&a.as_ref()) => {
+                    self.next();
+                    let args = self.args()?;
+                    items.push(Node::Call(a, args));
                 }
-                _ => {}
+
+                // if neither of these follow an identifier,
+                // it must just be a reference to a variable.
+                _ => items.push(Node::Var(a)),
+            },
+            Token::LambdaStart => {
+                let body = self.operand("no block after lambda")?;
+                items.push(Node::Lambda(Box::new(unwrap_block(body))));
+            }
+            Token::StringLiteral(s) => items.push(Node::Value(Raw::Text(s))),
+            Token::Integer(n) => items.push(Node::Value(Raw::Integer(n))),
+            Token::Real(n) => items.push(Node::Value(Raw::Real(n))),
+            Token::BinaryOperation(op_name) => {
+                let left = items.pop().ok_or_else(|| "add what dude?".to_string())?;
+                let right = self.operand(&format!("can't {} nothing", op_name))?;
+                items.push(Node::Call(op_name, vec![left, right]));
             }
+            token => return Err(format!("didn't expect {:?} there", token)),
         }
-        Ok(ast
-            .into_iter()
-            .map(|node| match node {
-                Node::Block(mut children) => {
-                    if children.len() == 1 {
-                        children.pop().unwrap()
-                    } else {
-                        Node::Block(children)
-                    }
+        Ok(())
+    }
+
+    /// Parses exactly one item, for things like operators that need something to work on.
+    fn operand(&mut self, missing: &str) -> Result<Node, String> {
+        let mut items = Ast::new();
+        if !self.tokens.is_empty() {
+            self.item(&mut items)?;
+        }
+        items.pop().ok_or_else(|| missing.to_string())
+    }
+}
+
+/// Splits the items found on a line up into statements. A control structure ends with its block,
+/// or with the block after its `ELSE`, so something like `IF a { RETURN(0) } RETURN(1)` is two
+/// statements. Anything else takes up the rest of the line.
+fn statements(mut items: Ast) -> Ast {
+    let mut statements = Ast::new();
+    while !items.is_empty() {
+        let end = match &items[0] {
+            Node::Var(name) if KEYWORDS.contains(&name.as_ref()) => {
+                let block =
+                    |from: usize| (from..items.len()).find(|&i| matches!(items[i], Node::Block(_)));
+                match block(1) {
+                    Some(end) => match items.get(end + 1) {
+                        Some(Node::Var(word)) if name == "IF" && word == "ELSE" => {
+                            block(end + 2).unwrap_or(items.len() - 1)
+                        }
+                        _ => end,
+                    },
+                    None => items.len() - 1,
+                }
+            }
+            _ => items.len() - 1,
+        };
+        let rest = items.split_off(end + 1);
+        statements.extend(statement(std::mem::replace(&mut items, rest)));
+    }
+    statements
+}
+
+/// Combines the items that make up a statement into one.
+fn statement(mut items: Ast) -> Option<Node> {
+    match items.len() {
+        0 => None,
+        1 => items.pop(),
+        _ => match items.remove(0) {
+            // A line starting with a word, like `IF a = b { ... }` or `REPEAT 3 TIMES { ... }`,
+            // calls that word with everything after it. Blocks passed like this aren't run right
+            // away, so they become lambdas.
+            Node::Var(name) => Some(Node::Call(
+                name,
+                items
+                    .into_iter()
+                    .map(|item| match item {
+                        Node::Block(_) => Node::Lambda(Box::new(unwrap_block(item))),
+                        item => item,
+                    })
+                    .collect(),
+            )),
+            first => {
+                items.insert(0, first);
+                Some(Node::Block(items))
+            }
+        },
+    }
+}
+
+/// Adds a statement to a block, marking the line it's from if it's on a new one. The spec puts an
+/// `ELSE`, and the curly braces after a control structure, on lines of their own, so these are
+/// added to the statement before them instead.
+fn push_statement(statements: &mut Ast, statement: Node, new_line: Option<u32>) {
+    if let Some(Node::Call(name, args)) = statements.last_mut() {
+        let waiting = KEYWORDS.contains(&name.as_ref())
+            && !matches!(args.last(), None | Some(Node::Lambda(_)));
+        if waiting && matches!(statement, Node::Block(_)) {
+            args.push(Node::Lambda(Box::new(unwrap_block(statement))));
+            return;
+        }
+
+        let is_else = matches!(&statement, Node::Var(id) | Node::Call(id, _) if id == "ELSE");
+        if name == "IF" && args.len() == 2 && is_else {
+            match statement {
+                Node::Call(else_name, else_args) => {
+                    args.push(Node::Var(else_name));
+                    args.extend(else_args);
                 }
-                a => a,
-            })
-            .collect())
+                else_name => args.push(else_name),
+            }
+            return;
+        }
+    }
+    statements.extend(new_line.map(Node::Line));
+    statements.push(statement);
+}
+
+/// Blocks with only one thing inside of them are replaced by that thing.
+fn unwrap_block(node: Node) -> Node {
+    match node {
+        Node::Block(mut children) if children.len() == 1 => children.pop().unwrap(),
+        node => node,
     }
 }
 
 /// Takes source code, turns it into tokens, creates a new parser, passes it the tokens,
 /// parses them into an AST, and returns said AST.
 pub fn parse<S: Into<String>>(src: S) -> Result<Node, String> {
-    Parser::new(super::tokenize(src.into())?)
-        .parse(Ast::new())?
-        .pop()
-        .ok_or("no output".to_string())
+    let mut parser = Parser::new(super::lex::tokenize_lines(src.into())?);
+    match parser.next() {
+        Some(Token::BlockOpen) => Ok(unwrap_block(Node::Block(parser.block()?))),
+        _ => Err("no output".to_string()),
+    }
 }
 
 #[test]
@@ -162,28 +260,26 @@ fn test_parse() {
 
     assert_eq!(
         parse("s <- 3"),
-        Ok(Assign("s".to_string(), Box::new(Value(Raw::Number(3.0))))),
+        Ok(Assign("s".to_string(), Box::new(Value(Raw::Integer(3))))),
     );
 
     assert_eq!(
         parse("s<-3"),
-        Ok(Assign("s".to_string(), Box::new(Value(Raw::Number(3.0))))),
+        Ok(Assign("s".to_string(), Box::new(Value(Raw::Integer(3))))),
     );
 
-    assert_eq!(
-        parse("IF true { DISPLAY(\"hi\") }"),
-        #[rustfmt::skip]
-        Ok(Call(
-            "IF".to_string(),
-            vec![
-                Node::Var("true".to_string()),
-                Node::Lambda(Box::new(Call(
-                    "DISPLAY".to_string(), 
-                    vec!(Node::Value(Raw::Text("hi".to_string()))),
-                )))
-            ]
-        )),
-    );
+    #[rustfmt::skip]
+    let expected = Ok(Call(
+        "IF".to_string(),
+        vec![
+            Node::Var("true".to_string()),
+            Node::Lambda(Box::new(Call(
+                "DISPLAY".to_string(), 
+                vec!(Node::Value(Raw::Text("hi".to_string()))),
+            )))
+        ]
+    ));
+    assert_eq!(parse("IF true { DISPLAY(\"hi\") }"), expected);
 
     assert_eq!(
         parse("3+2+7"),
@@ -192,9 +288,9 @@ fn test_parse() {
             vec!(
                 Call(
                     "+".to_string(),
-                    vec!(Value(Raw::Number(3.0)), Value(Raw::Number(2.0)),),
+                    vec!(Value(Raw::Integer(3)), Value(Raw::Integer(2)),),
                 ),
-                Value(Raw::Number(7.0)),
+                Value(Raw::Integer(7)),
             )
         )),
     );
@@ -208,9 +304,9 @@ fn test_parse() {
                 vec![
                     Call(
                         "+".to_string(),
-                        vec!(Value(Raw::Number(3.0)), Value(Raw::Number(2.0)),),
+                        vec!(Value(Raw::Integer(3)), Value(Raw::Integer(2)),),
                     ),
-                    Value(Raw::Number(7.0)),
+                    Value(Raw::Integer(7)),
                 ]
             )),
         ))
@@ -223,9 +319,9 @@ fn test_parse() {
             vec![
                 Call(
                     "-".to_string(),
-                    vec![Value(Raw::Number(100.0)), Value(Raw::Number(42.0)),]
+                    vec![Value(Raw::Integer(100)), Value(Raw::Integer(42)),]
                 ),
-                Value(Raw::Number(1.0)),
+                Value(Raw::Integer(1)),
             ]
         )),
     );
@@ -236,7 +332,8 @@ fn test_parse() {
              DISPLAY(s)"
         ),
         Ok(Block(vec![
-            Assign("s".to_string(), Box::new(Value(Raw::Number(3.0)))),
+            Assign("s".to_string(), Box::new(Value(Raw::Integer(3)))),
+            Line(2),
             Call("DISPLAY".to_string(), vec!(Var("s".to_string())),)
         ])),
     );
@@ -256,27 +353,152 @@ fn test_parse() {
         "
         ),
         Ok(Block(vec![
-            Assign("s".to_string(), Box::new(Value(Raw::Number(3.0)))),
-            Assign("l".to_string(), Box::new(Value(Raw::Number(4.0)))),
-            Assign("a".to_string(), Box::new(Value(Raw::Number(1.0)))),
+            Assign("s".to_string(), Box::new(Value(Raw::Integer(3)))),
+            Line(2),
+            Assign("l".to_string(), Box::new(Value(Raw::Integer(4)))),
+            Line(3),
+            Assign("a".to_string(), Box::new(Value(Raw::Integer(1)))),
+            Line(4),
             Assign(
                 "s".to_string(),
                 Box::new(Call(
                     "+".to_string(),
-                    vec![Var("a".to_string()), Value(Raw::Number(5.0))],
+                    vec![Var("a".to_string()), Value(Raw::Integer(5))],
                 ))
             ),
+            Line(5),
             Assign("l".to_string(), Box::new(Var("a".to_string()))),
+            Line(6),
             Assign(
                 "a".to_string(),
                 Box::new(Call(
                     "+".to_string(),
-                    vec!(Var("a".to_string()), Value(Raw::Number(3.0))),
+                    vec!(Var("a".to_string()), Value(Raw::Integer(3))),
                 ))
             ),
+            Line(7),
             Call("DISPLAY".to_string(), vec!(Var("s".to_string()))),
+            Line(8),
             Call("DISPLAY".to_string(), vec!(Var("l".to_string()))),
+            Line(9),
             Call("DISPLAY".to_string(), vec!(Var("a".to_string()))),
         ])),
     );
+
+    assert_eq!(
+        parse(
+            "\
+            x <- | { DISPLAY(\"hi\") }
+            x()
+            "
+        ),
+        Ok(Block(vec![
+            Assign(
+                "x".to_string(),
+                Box::new(Lambda(Box::new(Call(
+                    "DISPLAY".to_string(),
+                    vec![Value(Raw::Text("hi".to_string()))],
+                )))),
+            ),
+            Line(2),
+            Call("x".to_string(), vec![]),
+        ])),
+    );
+
+    assert_eq!(
+        parse(
+            "\
+            IF (a = 1) {
+                DISPLAY(a)
+            }
+            ELSE {
+                a <- 1
+                DISPLAY(0)
+            }\
+        "
+        ),
+        Ok(Call(
+            "IF".to_string(),
+            vec![
+                List(vec![Call(
+                    "=".to_string(),
+                    vec![Var("a".to_string()), Value(Raw::Integer(1))],
+                )]),
+                Lambda(Box::new(Block(vec![
+                    Line(2),
+                    Call("DISPLAY".to_string(), vec![Var("a".to_string())]),
+                ]))),
+                Var("ELSE".to_string()),
+                Lambda(Box::new(Block(vec![
+                    Line(5),
+                    Assign("a".to_string(), Box::new(Value(Raw::Integer(1)))),
+                    Line(6),
+                    Call("DISPLAY".to_string(), vec![Value(Raw::Integer(0))]),
+                ]))),
+            ],
+        )),
+    );
+
+    // statements after a control structure's block are statements of their own.
+    let n = || Var("n".to_string());
+    assert_eq!(
+        parse("PROCEDURE f(n) { IF n = 0 { RETURN(0) } RETURN(f(n - 1)) }"),
+        Ok(Call(
+            "PROCEDURE".to_string(),
+            vec![
+                Call("f".to_string(), vec![n()]),
+                Lambda(Box::new(Block(vec![
+                    Call(
+                        "IF".to_string(),
+                        vec![
+                            Call("=".to_string(), vec![n(), Value(Raw::Integer(0))]),
+                            Lambda(Box::new(Call(
+                                "RETURN".to_string(),
+                                vec![Value(Raw::Integer(0))],
+                            ))),
+                        ],
+                    ),
+                    Call(
+                        "RETURN".to_string(),
+                        vec![Call(
+                            "f".to_string(),
+                            vec![Call("-".to_string(), vec![n(), Value(Raw::Integer(1))])],
+                        )],
+                    ),
+                ]))),
+            ],
+        )),
+    );
+    assert_eq!(
+        parse("IF a { DISPLAY(1) } ELSE { DISPLAY(2) } DISPLAY(3)").map(|node| match node {
+            Block(statements) => statements.iter().map(|s| s.control().is_some()).collect(),
+            _ => vec![],
+        }),
+        Ok(vec![true, false])
+    );
+    assert_eq!(
+        parse("REPEAT 2 TIMES { a <- a + 1 } DISPLAY(a)").map(|node| match node {
+            Block(statements) => statements.len(),
+            _ => 0,
+        }),
+        Ok(2)
+    );
+
+    let repeat = parse("REPEAT UNTIL a > 3 { a <- a + 1 }").unwrap();
+    assert_eq!(
+        repeat.control(),
+        Some(Control::RepeatUntil(
+            &Call(
+                ">".to_string(),
+                vec![Var("a".to_string()), Value(Raw::Integer(3))],
+            ),
+            &Assign(
+                "a".to_string(),
+                Box::new(Call(
+                    "+".to_string(),
+                    vec![Var("a".to_string()), Value(Raw::Integer(1))],
+                )),
+            ),
+        )),
+    );
 }